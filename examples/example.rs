@@ -8,6 +8,7 @@ use proton_lite::eval::evaluate;
 use proton_lite::expr::{Expr, to_string};
 use proton_lite::differentiate::differentiate;
 use proton_lite::simplify::simplify;
+use proton_lite::parse::parse;
 
 fn test_operations() {
     // Build an expression like x + 2
@@ -182,7 +183,28 @@ fn test_operations() {
     println!("After: {}", to_string(&expr));
 }
 
+fn parse_expressions() {
+    // Instead of nesting Box::new(...) by hand, expressions can be parsed from strings
+    let expr: Expr = parse("x ^ 2 + 3 * sin(x)").unwrap();
+
+    let mut vars: HashMap<String, f64> = HashMap::new();
+    vars.insert("x".to_string(), 2.0);
+
+    println!("Expression: {}, x = {:?}", to_string(&expr), vars.get("x")); // prints ((x ^ 2) + (3 * sin(x)))
+    println!("Result: {}", evaluate(&expr, &vars));
+
+    let d = simplify(&differentiate(&expr, "x"));
+    println!("Derivative: {}", to_string(&d));
+
+    // Errors point at the offending part of the input
+    let source = "max(x, 2";
+    if let Err(err) = parse(source) {
+        print!("{}", err.render(source));
+    }
+}
+
 
 fn main() {
     test_operations();
+    parse_expressions();
 }
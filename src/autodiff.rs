@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use crate::error::ProtonError;
+use crate::eval::{EvalContext, UnboundPolicy};
+use crate::expr::Expr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueAndPartials {
+    pub value: f64,
+    pub partials: HashMap<String, f64>, // ∂expr/∂v for every variable v in the expression
+}
+
+// One node of the tape: its value and, for every operand that depends on a
+// variable, the operand's index and ∂node/∂operand
+struct Node {
+    value: f64,
+    constant: bool, // no variable below this node, so nothing flows back through it
+    operands: Vec<(usize, f64)>,
+}
+
+struct Tape<'a, 'b> {
+    context: &'b EvalContext<'a>,
+    nodes: Vec<Node>,
+    leaves: HashMap<String, usize>, // every occurrence of a variable shares one node
+}
+
+impl Tape<'_, '_> {
+    fn push(&mut self, value: f64, operands: Vec<(usize, f64)>) -> usize {
+        let constant = operands.iter().all(|&(index, _)| self.nodes[index].constant);
+        let operands = operands
+            .into_iter()
+            .filter(|&(index, _)| !self.nodes[index].constant)
+            .collect();
+        self.nodes.push(Node { value, constant, operands });
+        self.nodes.len() - 1
+    }
+
+    // The forward pass: record every node with its local partials
+    fn record(&mut self, expr: &Expr) -> Result<usize, ProtonError> {
+        let index = match expr {
+            Expr::Number(n) => {
+                self.nodes.push(Node { value: *n, constant: true, operands: Vec::new() });
+                self.nodes.len() - 1
+            }
+            Expr::Variable(name) => {
+                if let Some(&index) = self.leaves.get(name) {
+                    return Ok(index);
+                }
+                let value = self.context.variable(name)?;
+                self.nodes.push(Node { value, constant: false, operands: Vec::new() });
+                self.leaves.insert(name.clone(), self.nodes.len() - 1);
+                self.nodes.len() - 1
+            }
+            Expr::Add(left, right) => {
+                let (l, r) = (self.record(left)?, self.record(right)?);
+                self.push(self.nodes[l].value + self.nodes[r].value, vec![(l, 1.0), (r, 1.0)])
+            }
+            Expr::Sub(left, right) => {
+                let (l, r) = (self.record(left)?, self.record(right)?);
+                self.push(self.nodes[l].value - self.nodes[r].value, vec![(l, 1.0), (r, -1.0)])
+            }
+            Expr::Mul(left, right) => {
+                let (l, r) = (self.record(left)?, self.record(right)?);
+                let (a, b) = (self.nodes[l].value, self.nodes[r].value);
+                self.push(a * b, vec![(l, b), (r, a)])
+            }
+            Expr::Div(left, right) => {
+                let (l, r) = (self.record(left)?, self.record(right)?);
+                let (a, b) = (self.nodes[l].value, self.nodes[r].value);
+                self.push(a / b, vec![(l, 1.0 / b), (r, -a / (b * b))])
+            }
+            Expr::Pow(base, power) => {
+                let (l, r) = (self.record(base)?, self.record(power)?);
+                let (a, b) = (self.nodes[l].value, self.nodes[r].value);
+                let value = a.powf(b);
+                // x^0 is constant even at x = 0, where b * a^(b - 1) would be 0 * inf
+                let d_base = if b == 0.0 { 0.0 } else { b * a.powf(b - 1.0) };
+                // Only needed, and only defined for a > 0, when the exponent varies
+                let d_power = if self.nodes[r].constant { 0.0 } else { value * a.ln() };
+                self.push(value, vec![(l, d_base), (r, d_power)])
+            }
+            Expr::Func(name, args) => {
+                let indices = args
+                    .iter()
+                    .map(|arg| self.record(arg))
+                    .collect::<Result<Vec<usize>, ProtonError>>()?;
+                let values: Vec<f64> = indices.iter().map(|&i| self.nodes[i].value).collect();
+                let value = self.context.functions.call(name, &values)?;
+
+                let mut operands = Vec::new();
+                for (i, &index) in indices.iter().enumerate() {
+                    if !self.nodes[index].constant {
+                        operands.push((index, self.partial(name, &values, i)?));
+                    }
+                }
+                self.push(value, operands)
+            }
+        };
+        Ok(index)
+    }
+
+    // ∂name/∂arg_i at `values`, by evaluating the function's symbolic partial
+    // with the same function table
+    fn partial(&self, name: &str, values: &[f64], i: usize) -> Result<f64, ProtonError> {
+        let args: Vec<Expr> = values.iter().map(|&value| Expr::Number(value)).collect();
+        let partial = self.context.functions.lookup(name)?.partial(&args, i).ok_or_else(|| {
+            ProtonError::UnsupportedFunction {
+                name: name.to_string(),
+                operation: "differentiate",
+            }
+        })?;
+        EvalContext::new(&HashMap::new())
+            .with_functions(self.context.functions)
+            .evaluate(&partial)
+    }
+}
+
+// The value of `expr` and its partial derivative with respect to every
+// variable, from one forward and one backward pass instead of one symbolic
+// derivative per variable. Unbound variables evaluate to NaN, as in `evaluate`.
+pub fn gradient_at(expr: &Expr, vars: &HashMap<String, f64>) -> ValueAndPartials {
+    let context = EvalContext::new(vars).with_policy(UnboundPolicy::Nan);
+    try_gradient_at_with(expr, &context).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_gradient_at(expr: &Expr, vars: &HashMap<String, f64>) -> Result<ValueAndPartials, ProtonError> {
+    try_gradient_at_with(expr, &EvalContext::new(vars))
+}
+
+// Reverse-mode differentiation with the variables, unbound policy and
+// function table of `context`
+pub fn try_gradient_at_with(expr: &Expr, context: &EvalContext) -> Result<ValueAndPartials, ProtonError> {
+    let expr = context.expand(expr)?;
+    let mut tape = Tape {
+        context,
+        nodes: Vec::new(),
+        leaves: HashMap::new(),
+    };
+    let output = tape.record(&expr)?;
+
+    // The backward pass: nodes are recorded after their operands, so walking
+    // the tape backwards visits every node after everything that uses it
+    let mut adjoints = vec![0.0; tape.nodes.len()];
+    adjoints[output] = 1.0;
+    for index in (0..tape.nodes.len()).rev() {
+        let adjoint = adjoints[index];
+        if adjoint == 0.0 {
+            continue;
+        }
+        for &(operand, partial) in &tape.nodes[index].operands {
+            adjoints[operand] += adjoint * partial;
+        }
+    }
+
+    Ok(ValueAndPartials {
+        value: tape.nodes[output].value,
+        partials: tape
+            .leaves
+            .into_iter()
+            .map(|(name, index)| (name, adjoints[index]))
+            .collect(),
+    })
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::eval::try_evaluate;
+use crate::expr::{variables, Expr};
+
+#[derive(Debug)]
+pub enum BatchError {
+    Io(io::Error),
+    EmptyInput, // not even a header row
+    MissingColumns(Vec<String>), // variables of the expression with no column in the header
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Io(err) => write!(f, "{}", err),
+            BatchError::EmptyInput => write!(f, "input has no header row"),
+            BatchError::MissingColumns(names) => write!(f, "no column for variable(s): {}", names.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl From<io::Error> for BatchError {
+    fn from(err: io::Error) -> Self {
+        BatchError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+    pub line: usize, // 1-based line in the input, the header is line 1
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BatchSummary {
+    pub rows: usize,
+    pub errors: Vec<RowError>,
+}
+
+// Evaluate `expr` once per row of a CSV whose header names the variables,
+// with the same semantics as `eval::try_evaluate`, except that a NaN result
+// counts as a failed row.
+// The output repeats every input column and appends `result_column` plus an
+// `error` column; a row that fails keeps an empty result and the reason,
+// so one bad row never aborts the run.
+pub fn evaluate_csv<R: BufRead, W: Write>(
+    expr: &Expr,
+    input: R,
+    mut output: W,
+    result_column: &str,
+) -> Result<BatchSummary, BatchError> {
+    let mut records = Records { lines: input.lines(), line: 0 };
+    let header = match records.next() {
+        Some(record) => split_record(&record?.1),
+        None => return Err(BatchError::EmptyInput),
+    };
+
+    // Only the columns the expression uses need to hold numbers
+    let mut used = Vec::new();
+    let mut missing = Vec::new();
+    for name in variables(expr) {
+        match header.iter().position(|column| column.trim() == name) {
+            Some(index) => used.push((name, index)),
+            None => missing.push(name),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(BatchError::MissingColumns(missing));
+    }
+
+    let mut out_header = header.clone();
+    out_header.push(result_column.to_string());
+    out_header.push("error".to_string());
+    writeln!(output, "{}", join_record(&out_header))?;
+
+    let mut summary = BatchSummary::default();
+    let mut vars: HashMap<String, f64> = HashMap::new();
+    for record in records {
+        let (number, line) = record?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        summary.rows += 1;
+
+        let mut record = split_record(&line);
+        let result = evaluate_row(expr, &header, &record, &used, &mut vars);
+
+        // Pad or cut malformed rows so the output stays rectangular
+        record.resize(header.len(), String::new());
+        match result {
+            Ok(value) => {
+                record.push(value.to_string());
+                record.push(String::new());
+            }
+            Err(message) => {
+                record.push(String::new());
+                record.push(message.clone());
+                summary.errors.push(RowError { line: number, message });
+            }
+        }
+        writeln!(output, "{}", join_record(&record))?;
+    }
+
+    Ok(summary)
+}
+
+fn evaluate_row(
+    expr: &Expr,
+    header: &[String],
+    record: &[String],
+    used: &[(String, usize)],
+    vars: &mut HashMap<String, f64>,
+) -> Result<f64, String> {
+    if record.len() != header.len() {
+        return Err(format!("expected {} fields, found {}", header.len(), record.len()));
+    }
+
+    vars.clear();
+    for (name, index) in used {
+        let cell = record[*index].trim();
+        let value: f64 = cell
+            .parse()
+            .map_err(|_| format!("`{}` in column `{}` is not a number", cell, name))?;
+        vars.insert(name.clone(), value);
+    }
+
+    match try_evaluate(expr, vars) {
+        Ok(value) if value.is_nan() => Err("result is not a number".to_string()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Input lines joined into whole records, since a quoted field may span
+// lines, as `join_record` writes fields that contain newlines. Each record
+// comes with the line it starts on
+struct Records<R> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = io::Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        self.line += 1;
+        let start = self.line;
+
+        // An odd number of quotes so far leaves a field open; `""` escapes
+        // count twice and don't change that
+        while record.matches('"').count() % 2 == 1 {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line += 1;
+                    record.push('\n');
+                    record.push_str(&line);
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                // An unterminated quote runs to the end of the input
+                None => break,
+            }
+        }
+        Some(Ok((start, record)))
+    }
+}
+
+// Split one CSV record into fields, honouring double-quoted fields with `""` escapes
+fn split_record(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek().is_none() => {}
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn join_record(fields: &[String]) -> String {
+    let quoted: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    quoted.join(",")
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::error::ProtonError;
+use crate::eval::EvalContext;
+use crate::expr::{substitute, variables, Expr};
+use crate::finite_difference::Estimate;
+use crate::integrate::try_integrate_with;
+use crate::quadrature::{quadrature_with, Method};
+use crate::registry::{builtins, FunctionRegistry};
+use crate::simplify::try_simplify_with;
+
+// F(upper) - F(lower) is trusted when it is within AGREEMENT times the
+// quadrature's error estimate, allowing TOLERANCE relative error on top
+const AGREEMENT: f64 = 100.0;
+const TOLERANCE: f64 = 1e-8;
+
+// The result of a definite integral, and which path produced it
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefiniteIntegral {
+    Symbolic(Expr), // F(upper) - F(lower) from an antiderivative
+    Numeric(Estimate), // quadrature, for integrands without an antiderivative
+}
+
+pub fn integrate_definite(expr: &Expr, var: &str, lower: &Expr, upper: &Expr) -> DefiniteIntegral {
+    try_integrate_definite(expr, var, lower, upper).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_integrate_definite(expr: &Expr, var: &str, lower: &Expr, upper: &Expr) -> Result<DefiniteIntegral, ProtonError> {
+    try_integrate_definite_with(expr, var, lower, upper, builtins())
+}
+
+// ∫ expr d(var) from `lower` to `upper`. Bounds may be expressions in other
+// variables, as in ∫_0^t; those only have a symbolic answer. With numeric
+// bounds the integral is also estimated numerically, which both stands in
+// for a missing antiderivative and checks F(upper) - F(lower): a pole inside
+// [lower, upper] makes that difference meaningless, and shows up as a
+// non-finite value or a diverging estimate
+pub fn try_integrate_definite_with(
+    expr: &Expr,
+    var: &str,
+    lower: &Expr,
+    upper: &Expr,
+    functions: &FunctionRegistry,
+) -> Result<DefiniteIntegral, ProtonError> {
+    let no_vars = HashMap::new();
+    let context = EvalContext::new(&no_vars).with_functions(functions);
+    let bounds = match (context.evaluate(lower), context.evaluate(upper)) {
+        (Ok(a), Ok(b)) => Some((a, b)),
+        _ => None,
+    };
+
+    let antiderivative = match try_integrate_with(expr, var, functions) {
+        Ok(antiderivative) => antiderivative,
+        Err(ProtonError::NoClosedForm(_) | ProtonError::UnsupportedFunction { .. }) if let Some((a, b)) = bounds => {
+            return Ok(DefiniteIntegral::Numeric(quadrature(expr, var, a, b, functions)?));
+        }
+        Err(err) => return Err(err),
+    };
+
+    let at = |bound: &Expr| substitute(&antiderivative, &HashMap::from([(var.to_string(), bound.clone())]));
+    let value = try_simplify_with(&Expr::Sub(Box::new(at(upper)), Box::new(at(lower))), functions)?;
+
+    // Integrands with other variables can't be estimated, so the
+    // antiderivative goes unchecked
+    let Some((a, b)) = bounds else {
+        return Ok(DefiniteIntegral::Symbolic(value));
+    };
+    if variables(expr).iter().any(|name| name != var) {
+        return Ok(DefiniteIntegral::Symbolic(value));
+    }
+    let exact = match value {
+        Expr::Number(n) if n.is_finite() => Some(n),
+        _ => None,
+    };
+    // An antiderivative that is continuous across [a, b] agrees with the
+    // estimate; one that jumps, say across a branch of atan, doesn't
+    let agrees = |n: f64, estimate: f64, error: f64| (n - estimate).abs() <= AGREEMENT * (error + TOLERANCE * n.abs().max(1.0));
+    match (quadrature(expr, var, a, b, functions), exact) {
+        (Ok(estimate), Some(n)) if agrees(n, estimate.value, estimate.error) => Ok(DefiniteIntegral::Symbolic(value)),
+        (Ok(estimate), _) => Ok(DefiniteIntegral::Numeric(estimate)),
+        // Slow convergence, like cos(x) over [0, 1e6], says nothing against F
+        // unless the estimate is off by more than its own error; a pole
+        // inside shows up as `Diverged` instead
+        (Err(ProtonError::NotConverged { value: estimate, error }), Some(n)) if agrees(n, estimate, error) => {
+            Ok(DefiniteIntegral::Symbolic(value))
+        }
+        // Infinite at a bound, even for tanh-sinh, but finite there once
+        // integrated: only F can reach the bound
+        (Err(ProtonError::NonFiniteValue { at, .. }), Some(_)) if at == a || at == b => Ok(DefiniteIntegral::Symbolic(value)),
+        (Err(err), _) => Err(err),
+    }
+}
+
+// Adaptive Gauss–Kronrod over [a, b], which may be infinite. Its outermost
+// nodes round onto a bound for integrands like 1 / sqrt(1 - x^2) on [-1, 1];
+// tanh-sinh stays off the bounds and copes with such singularities
+fn quadrature(expr: &Expr, var: &str, a: f64, b: f64, functions: &FunctionRegistry) -> Result<Estimate, ProtonError> {
+    let no_vars = HashMap::new();
+    let context = EvalContext::new(&no_vars).with_functions(functions);
+    match quadrature_with(expr, var, a, b, &context, Method::GaussKronrod21) {
+        Err(ProtonError::NonFiniteValue { at, .. }) if at == a || at == b => quadrature_with(expr, var, a, b, &context, Method::TanhSinh),
+        result => result,
+    }
+}
@@ -1,9 +1,21 @@
-use crate::expr::{to_string, Expr};
+use crate::error::ProtonError;
+use crate::expr::{contains_variable, Expr};
+use crate::registry::{builtins, FunctionRegistry};
+use crate::simplify::try_simplify_with;
 
 
-#[allow(unreachable_patterns)]
 pub fn differentiate(expr: &Expr, var: &str) -> Expr {
-    match expr {
+    try_differentiate(expr, var).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Like `differentiate`, but functions without a derivative rule are reported as errors
+pub fn try_differentiate(expr: &Expr, var: &str) -> Result<Expr, ProtonError> {
+    try_differentiate_with(expr, var, builtins())
+}
+
+// Differentiate using the derivative rules registered in `functions`
+pub fn try_differentiate_with(expr: &Expr, var: &str, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    let derivative = match expr {
         // derivative of a constant = 0
         Expr::Number(_) => Expr::Number(0.0),
         
@@ -18,25 +30,25 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
 
         // (f + g)' = f' + g'
         Expr::Add(left, right) => Expr::Add(
-            Box::new(differentiate(left, var)),
-            Box::new(differentiate(right, var))
+            Box::new(try_differentiate_with(left, var, functions)?),
+            Box::new(try_differentiate_with(right, var, functions)?)
         ),
 
         // (f - g)' = f' - g'
         Expr::Sub(left, right) => Expr::Sub(
-            Box::new(differentiate(&left, var)),
-            Box::new(differentiate(&right, var))
+            Box::new(try_differentiate_with(left, var, functions)?),
+            Box::new(try_differentiate_with(right, var, functions)?)
         ),
 
         // (f * g)' = f'g + fg'
         Expr::Mul(left, right) => Expr::Add(
             Box::new(Expr::Mul(
-                Box::new(differentiate(&left, var)),
+                Box::new(try_differentiate_with(left, var, functions)?),
                 right.clone(),
             )),
             Box::new(Expr::Mul(
                 left.clone(),
-                Box::new(differentiate(&right, var)),
+                Box::new(try_differentiate_with(right, var, functions)?),
             )),
         ),
 
@@ -46,14 +58,14 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
                 Expr::Sub(
                     Box::new(
                         Expr::Mul(
-                            Box::new(differentiate(&left, var)),
+                            Box::new(try_differentiate_with(left, var, functions)?),
                             right.clone(),
                         )
                     ),
                     Box::new(
                         Expr::Mul(
                             left.clone(),
-                            Box::new(differentiate(&right, var)),
+                            Box::new(try_differentiate_with(right, var, functions)?),
                         ),
                     ),
                 )
@@ -68,114 +80,109 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
             ),
         ),
 
-        Expr::Pow(base, exponent ) => {
-            match (&** base, &** exponent) { 
+        Expr::Pow(base, exponent) => {
+            let pow = |f: &Expr, g: Expr| Expr::Pow(Box::new(f.clone()), Box::new(g));
+            let mul = |left: Expr, right: Expr| Expr::Mul(Box::new(left), Box::new(right));
+            let ln = |f: &Expr| Expr::Func("ln".to_string(), vec![f.clone()]);
+
+            match (contains_variable(base, var), contains_variable(exponent, var)) {
+                // Neither side depends on the variable
+                (false, false) => Expr::Number(0.0),
+
                 // (f ^ n)' = n * f ^ (n - 1) * f'
-                (_, Expr::Number(n)) => Expr::Mul(
-                    Box::new(
-                        Expr::Mul(
-                            Box::new(Expr::Number(*n)),
-                            Box::new(
-                                Expr::Pow(
-                                    base.clone(),
-                                    Box::new(Expr::Number(*n - 1.0)),
-                                )
-                            )
-                        )
-                    ),
-                    Box::new(differentiate(&base, var)),
+                (true, false) => {
+                    let n_minus_one = match &**exponent {
+                        Expr::Number(n) => Expr::Number(n - 1.0),
+                        g => Expr::Sub(Box::new(g.clone()), Box::new(Expr::Number(1.0))),
+                    };
+                    mul(
+                        mul((**exponent).clone(), pow(base, n_minus_one)),
+                        try_differentiate_with(base, var, functions)?,
+                    )
+                }
+
+                // (a ^ g)' = a ^ g * ln(a) * g'
+                (false, true) => mul(
+                    mul(expr.clone(), ln(base)),
+                    try_differentiate_with(exponent, var, functions)?,
                 ),
-                // General case: (f ^ g)' = f^g * (g' * ln(f) + g * f'/f)
-                _ => {
-                    let f = base.clone();
-                    let g = exponent.clone();
-                    let f_prime = differentiate(&f, var);
-                    let g_prime = differentiate(&g, var);
-                    let ln_f = Expr::Func(
-                        "ln".to_string(),
-                        vec![*f.clone()],
+
+                // (f ^ g)' = f ^ g * (g' * ln(f) + g * f' / f)
+                (true, true) => {
+                    let f_prime = try_differentiate_with(base, var, functions)?;
+                    let g_prime = try_differentiate_with(exponent, var, functions)?;
+                    let g_prime_ln_f = mul(g_prime, ln(base));
+                    let g_f_prime_upon_f = mul(
+                        (**exponent).clone(),
+                        Expr::Div(Box::new(f_prime), base.clone()),
                     );
-                    let g_prime_ln_f = Expr::Mul(Box::new(g_prime), Box::new(ln_f));
-                    let f_prime_upon_f = Expr::Div(Box::new(f_prime), f.clone());
-                    let g_fprime_upon_f = Expr::Mul(g.clone(), Box::new(f_prime_upon_f));
-                    let product = Expr::Mul(Box::new(g_prime_ln_f), Box::new(g_fprime_upon_f));
-                    Expr::Mul(
-                        Box::new(Expr::Pow(f, g)),
-                        Box::new(product),
+                    mul(
+                        expr.clone(),
+                        Expr::Add(Box::new(g_prime_ln_f), Box::new(g_f_prime_upon_f)),
                     )
                 }
             }
         },
         
+        // (f(u1, u2, ...))' is given by the function's registered rule from the u's and their derivatives
         Expr::Func(name, args) => {
-            if args.len() != 1 {
-                panic!("Differentiation only supports single-argument functions currently");
+            let function = functions.lookup(name)?;
+            function.check_arity(args.len())?;
+
+            // User-defined functions are differentiated through their definition
+            if let Some(body) = functions.instantiate(name, args) {
+                return try_differentiate_with(&body, var, functions);
             }
 
-            let arg = &args[0];
-            let d_arg = differentiate(arg, var);
-            
-            match name.as_str() {
-                // (sin(x))' = cos(x)
-                "sin" => Expr::Mul(
-                    Box::new(
-                        Expr::Func(
-                            "cos".to_string(),
-                            vec![arg.clone()]
-                        )
-                    ),
-                    Box::new(d_arg),
-                ),
+            let rule = function.derivative.as_ref().ok_or_else(|| ProtonError::UnsupportedFunction {
+                name: name.clone(),
+                operation: "differentiate",
+            })?;
 
-                // (cos(x))' = -sin(x)
-                "cos" => Expr::Mul(
-                    Box::new(
-                        Expr::Mul(
-                            Box::new(Expr::Number(-1.0)),
-                            Box::new(
-                                Expr::Func(
-                                    "sin".to_string(),
-                                    vec![arg.clone()]
-                                )
-                            )
-                        )
-                    ),
-                Box::new(d_arg)
-                ),
-                
-
-                // (tan(x))' = sec^2(x) = 1 / cos^2(x)
-                "tan" => Expr::Mul(
-                    Box::new(Expr::Div(
-                        Box::new(Expr::Number(1.0)),
-                        Box::new(Expr::Pow(Box::new(Expr::Func("cos".into(), vec![arg.clone()])), Box::new(Expr::Number(2.0))))
-                    )),
-                    Box::new(d_arg)
-                ),
+            let d_args = args
+                .iter()
+                .map(|arg| try_differentiate_with(arg, var, functions))
+                .collect::<Result<Vec<Expr>, ProtonError>>()?;
+            rule(args, &d_args)
+        },
+    };
+    Ok(derivative)
+}
 
-                // (ln(x))' = 1 / x
-                "ln" => Expr::Div(
-                    Box::new(d_arg), 
-                    Box::new(arg.clone())
-                ),
+// The n-th derivative with respect to `var`, simplified after every step so
+// the tree stays small. n = 0 gives back `expr` simplified.
+pub fn differentiate_n(expr: &Expr, var: &str, n: usize) -> Expr {
+    try_differentiate_n(expr, var, n).unwrap_or_else(|err| panic!("{}", err))
+}
 
-                // (sqrt(x))' = 1/(2 * sqrt(x))
-                "sqrt" => Expr::Div(
-                    Box::new(d_arg),
-                    Box::new(
-                        Expr::Mul(
-                            Box::new(Expr::Number(2.0)),
-                            Box::new(Expr::Func("sqrt".into(), vec![arg.clone()])),
-                        )
-                    )
-                ),
+pub fn try_differentiate_n(expr: &Expr, var: &str, n: usize) -> Result<Expr, ProtonError> {
+    try_differentiate_n_with(expr, var, n, builtins())
+}
 
-                _ => {
-                    panic!("Unsupported Function: {}", name);
-                }
-            }
+pub fn try_differentiate_n_with(expr: &Expr, var: &str, n: usize, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    try_partial_with(expr, &[(var, n)], functions)
+}
 
-        },
-        _ => panic!("Unknown Function: {}", to_string(&expr)),
+// Mixed partial derivative, e.g. `partial(expr, &[("x", 2), ("y", 1)])` for
+// ∂³expr/∂x²∂y, taken in the order given and simplified after every step
+pub fn partial(expr: &Expr, orders: &[(&str, usize)]) -> Expr {
+    try_partial(expr, orders).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_partial(expr: &Expr, orders: &[(&str, usize)]) -> Result<Expr, ProtonError> {
+    try_partial_with(expr, orders, builtins())
+}
+
+pub fn try_partial_with(expr: &Expr, orders: &[(&str, usize)], functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    let mut result = try_simplify_with(expr, functions)?;
+    for &(var, n) in orders {
+        for _ in 0..n {
+            // Once everything has cancelled, further derivatives stay 0
+            if result == Expr::Number(0.0) {
+                return Ok(result);
+            }
+            result = try_simplify_with(&try_differentiate_with(&result, var, functions)?, functions)?;
+        }
     }
+    Ok(result)
 }
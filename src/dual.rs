@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::differentiate::try_differentiate_with;
+use crate::error::ProtonError;
+use crate::eval::{EvalContext, UnboundPolicy};
+use crate::expr::Expr;
+
+// a + b ε with ε² = 0: evaluating f at (x, 1) gives (f(x), f'(x))
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dual {
+    pub value: f64,
+    pub derivative: f64,
+}
+
+// a + b ε1 + c ε2 + d ε1ε2 with ε1² = ε2² = 0: seeding x with ε1 and y with
+// ε2 gives ∂f/∂x, ∂f/∂y and ∂²f/∂x∂y, or f'' when both seed the same variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HyperDual {
+    pub value: f64,
+    pub e1: f64,
+    pub e2: f64,
+    pub e1e2: f64,
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, other: Dual) -> Dual {
+        Dual { value: self.value + other.value, derivative: self.derivative + other.derivative }
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, other: Dual) -> Dual {
+        Dual { value: self.value - other.value, derivative: self.derivative - other.derivative }
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, other: Dual) -> Dual {
+        Dual {
+            value: self.value * other.value,
+            derivative: self.derivative * other.value + self.value * other.derivative,
+        }
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    fn div(self, other: Dual) -> Dual {
+        Dual {
+            value: self.value / other.value,
+            derivative: (self.derivative * other.value - self.value * other.derivative) / (other.value * other.value),
+        }
+    }
+}
+
+impl Add for HyperDual {
+    type Output = HyperDual;
+    fn add(self, other: HyperDual) -> HyperDual {
+        HyperDual {
+            value: self.value + other.value,
+            e1: self.e1 + other.e1,
+            e2: self.e2 + other.e2,
+            e1e2: self.e1e2 + other.e1e2,
+        }
+    }
+}
+
+impl Sub for HyperDual {
+    type Output = HyperDual;
+    fn sub(self, other: HyperDual) -> HyperDual {
+        HyperDual {
+            value: self.value - other.value,
+            e1: self.e1 - other.e1,
+            e2: self.e2 - other.e2,
+            e1e2: self.e1e2 - other.e1e2,
+        }
+    }
+}
+
+impl Mul for HyperDual {
+    type Output = HyperDual;
+    fn mul(self, other: HyperDual) -> HyperDual {
+        HyperDual {
+            value: self.value * other.value,
+            e1: self.e1 * other.value + self.value * other.e1,
+            e2: self.e2 * other.value + self.value * other.e2,
+            e1e2: self.e1e2 * other.value + self.e1 * other.e2 + self.e2 * other.e1 + self.value * other.e1e2,
+        }
+    }
+}
+
+impl Div for HyperDual {
+    type Output = HyperDual;
+    fn div(self, other: HyperDual) -> HyperDual {
+        // a / b = a * (1 / b), with 1 / b expanded like any other function
+        let b = other.value;
+        let reciprocal = HyperDual::chain(1.0 / b, &[other], &[-1.0 / (b * b)], &[vec![2.0 / (b * b * b)]]);
+        self * reciprocal
+    }
+}
+
+// Numbers that carry derivatives along with their value
+trait Jet: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> {
+    fn constant(value: f64) -> Self;
+    fn value(&self) -> f64;
+    // Does any derivative component depend on the seeded variables?
+    fn varies(&self) -> bool;
+    // f(args) from f's value, its first partials and, where needed, second partials
+    fn chain(value: f64, args: &[Self], first: &[f64], second: &[Vec<f64>]) -> Self;
+    const NEEDS_SECOND: bool;
+}
+
+impl Jet for Dual {
+    const NEEDS_SECOND: bool = false;
+
+    fn constant(value: f64) -> Self {
+        Dual { value, derivative: 0.0 }
+    }
+
+    fn value(&self) -> f64 {
+        self.value
+    }
+
+    fn varies(&self) -> bool {
+        self.derivative != 0.0
+    }
+
+    fn chain(value: f64, args: &[Self], first: &[f64], _: &[Vec<f64>]) -> Self {
+        let derivative = args
+            .iter()
+            .zip(first)
+            .filter(|(arg, _)| arg.varies())
+            .map(|(arg, partial)| partial * arg.derivative)
+            .sum();
+        Dual { value, derivative }
+    }
+}
+
+impl Jet for HyperDual {
+    const NEEDS_SECOND: bool = true;
+
+    fn constant(value: f64) -> Self {
+        HyperDual { value, e1: 0.0, e2: 0.0, e1e2: 0.0 }
+    }
+
+    fn value(&self) -> f64 {
+        self.value
+    }
+
+    fn varies(&self) -> bool {
+        self.e1 != 0.0 || self.e2 != 0.0 || self.e1e2 != 0.0
+    }
+
+    // e1e2 = Σ f_i d_i + Σ f_ij b_i c_j, the second-order chain rule
+    fn chain(value: f64, args: &[Self], first: &[f64], second: &[Vec<f64>]) -> Self {
+        let mut result = HyperDual::constant(value);
+        for (i, arg) in args.iter().enumerate().filter(|(_, arg)| arg.varies()) {
+            result.e1 += first[i] * arg.e1;
+            result.e2 += first[i] * arg.e2;
+            result.e1e2 += first[i] * arg.e1e2;
+            for (j, other) in args.iter().enumerate().filter(|(_, other)| other.varies()) {
+                if arg.e1 != 0.0 && other.e2 != 0.0 {
+                    result.e1e2 += second[i][j] * arg.e1 * other.e2;
+                }
+            }
+        }
+        result
+    }
+}
+
+struct Forward<'a, 'b, J> {
+    context: &'b EvalContext<'a>,
+    seed: &'b dyn Fn(&str, f64) -> J,
+}
+
+impl<J: Jet> Forward<'_, '_, J> {
+    fn evaluate(&self, expr: &Expr) -> Result<J, ProtonError> {
+        let value = match expr {
+            Expr::Number(n) => J::constant(*n),
+            Expr::Variable(name) => (self.seed)(name, self.context.variable(name)?),
+            Expr::Add(left, right) => self.evaluate(left)? + self.evaluate(right)?,
+            Expr::Sub(left, right) => self.evaluate(left)? - self.evaluate(right)?,
+            Expr::Mul(left, right) => self.evaluate(left)? * self.evaluate(right)?,
+            Expr::Div(left, right) => self.evaluate(left)? / self.evaluate(right)?,
+            Expr::Pow(base, power) => {
+                let (base, power) = (self.evaluate(base)?, self.evaluate(power)?);
+                let (a, b) = (base.value(), power.value());
+                // x^0 is constant even at x = 0, where the partials below are 0 * inf
+                if b == 0.0 && !power.varies() {
+                    return Ok(J::constant(1.0));
+                }
+                let ln = a.ln();
+                // Partials of a^b; terms for a constant exponent are skipped by
+                // `chain`, so ln(a) never matters for a <= 0 unless the exponent varies
+                let first = [b * a.powf(b - 1.0), a.powf(b) * ln];
+                let cross = a.powf(b - 1.0) * (1.0 + b * ln);
+                let second = [
+                    vec![b * (b - 1.0) * a.powf(b - 2.0), cross],
+                    vec![cross, a.powf(b) * ln * ln],
+                ];
+                J::chain(a.powf(b), &[base, power], &first, &second)
+            }
+            Expr::Func(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg))
+                    .collect::<Result<Vec<J>, ProtonError>>()?;
+                let values: Vec<f64> = args.iter().map(J::value).collect();
+                let value = self.context.functions.call(name, &values)?;
+                if !args.iter().any(J::varies) {
+                    return Ok(J::constant(value));
+                }
+                let (first, second) = self.partials(name, &values, J::NEEDS_SECOND)?;
+                J::chain(value, &args, &first, &second)
+            }
+        };
+        Ok(value)
+    }
+
+    // First, and optionally second, partials of `name` at `values`, from its
+    // symbolic derivative rule over placeholder arguments _0, _1, ...
+    fn partials(&self, name: &str, values: &[f64], second: bool) -> Result<(Vec<f64>, Vec<Vec<f64>>), ProtonError> {
+        let functions = self.context.functions;
+        let function = functions.lookup(name)?;
+        let unsupported = || ProtonError::UnsupportedFunction {
+            name: name.to_string(),
+            operation: "differentiate",
+        };
+
+        let names: Vec<String> = (0..values.len()).map(|i| format!("_{}", i)).collect();
+        let placeholders: Vec<Expr> = names.iter().map(|name| Expr::Variable(name.clone())).collect();
+        let bound: HashMap<String, f64> = names.iter().cloned().zip(values.iter().cloned()).collect();
+        let context = EvalContext::new(&bound).with_functions(functions);
+
+        let mut first = Vec::new();
+        let mut hessian = Vec::new();
+        for i in 0..values.len() {
+            let partial = function.partial(&placeholders, i).ok_or_else(unsupported)?;
+            first.push(context.evaluate(&partial)?);
+            if second {
+                let row = names
+                    .iter()
+                    .map(|name| context.evaluate(&try_differentiate_with(&partial, name, functions)?))
+                    .collect::<Result<Vec<f64>, ProtonError>>()?;
+                hessian.push(row);
+            }
+        }
+        Ok((first, hessian))
+    }
+}
+
+fn forward<J: Jet>(expr: &Expr, context: &EvalContext, seed: &dyn Fn(&str, f64) -> J) -> Result<J, ProtonError> {
+    let expr = context.expand(expr)?;
+    Forward { context, seed }.evaluate(&expr)
+}
+
+// f and ∂f/∂var at `vars` in a single pass, without building a symbolic
+// derivative. Unbound variables evaluate to NaN, as in `evaluate`.
+pub fn evaluate_dual(expr: &Expr, var: &str, vars: &HashMap<String, f64>) -> Dual {
+    let context = EvalContext::new(vars).with_policy(UnboundPolicy::Nan);
+    try_evaluate_dual_with(expr, var, &context).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_evaluate_dual(expr: &Expr, var: &str, vars: &HashMap<String, f64>) -> Result<Dual, ProtonError> {
+    try_evaluate_dual_with(expr, var, &EvalContext::new(vars))
+}
+
+pub fn try_evaluate_dual_with(expr: &Expr, var: &str, context: &EvalContext) -> Result<Dual, ProtonError> {
+    forward(expr, context, &|name, value| Dual {
+        value,
+        derivative: if name == var { 1.0 } else { 0.0 },
+    })
+}
+
+// f, ∂f/∂first, ∂f/∂second and ∂²f/∂first∂second at `vars` in a single pass;
+// pass the same variable twice for the second derivative
+pub fn evaluate_hyper_dual(expr: &Expr, first: &str, second: &str, vars: &HashMap<String, f64>) -> HyperDual {
+    let context = EvalContext::new(vars).with_policy(UnboundPolicy::Nan);
+    try_evaluate_hyper_dual_with(expr, first, second, &context).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_evaluate_hyper_dual(
+    expr: &Expr,
+    first: &str,
+    second: &str,
+    vars: &HashMap<String, f64>,
+) -> Result<HyperDual, ProtonError> {
+    try_evaluate_hyper_dual_with(expr, first, second, &EvalContext::new(vars))
+}
+
+pub fn try_evaluate_hyper_dual_with(
+    expr: &Expr,
+    first: &str,
+    second: &str,
+    context: &EvalContext,
+) -> Result<HyperDual, ProtonError> {
+    forward(expr, context, &|name, value| HyperDual {
+        value,
+        e1: if name == first { 1.0 } else { 0.0 },
+        e2: if name == second { 1.0 } else { 0.0 },
+        e1e2: 0.0,
+    })
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+use crate::parse::ParseError;
+use crate::registry::Arity;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtonError {
+    Parse(ParseError),
+    UnknownFunction(String), // no such function at all
+    UnsupportedFunction { name: String, operation: &'static str }, // known, but e.g. cannot be integrated yet
+    ArityMismatch { name: String, expected: Arity, found: usize },
+    UnboundVariables(Vec<String>), // every variable without a value
+    DivisionByZero, // from simplifying a literal x / 0; evaluation gives inf or NaN instead
+    NoClosedForm(String), // the integrand, printed with `to_string`
+    RecursiveDefinition(String), // a user-defined function that would call itself
+    InvalidDefinition(String),
+    DimensionMismatch { expected: usize, found: usize }, // e.g. a 2D field where a curl needs 3 components
+    NonFiniteValue { var: String, at: f64 }, // an integrand that is infinite or NaN at var = at
+    NotConverged { value: f64, error: f64 }, // a numeric method that ran out of evaluations, with its last estimate
+    Diverged { var: String, near: f64 }, // an integral that blows up near var = near, like 1 / x around 0
+    InvalidBound(f64), // a NaN integration bound; infinite ones are fine
+}
+
+impl fmt::Display for ProtonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtonError::Parse(err) => write!(f, "{}", err),
+            ProtonError::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            ProtonError::UnsupportedFunction { name, operation } => {
+                write!(f, "cannot {} function `{}`", operation, name)
+            }
+            ProtonError::ArityMismatch { name, expected, found } => {
+                write!(f, "{}() expects {} argument(s), found {}", name, expected, found)
+            }
+            ProtonError::UnboundVariables(names) => {
+                let names: Vec<String> = names.iter().map(|name| format!("`{}`", name)).collect();
+                write!(f, "unbound variable(s) {}", names.join(", "))
+            }
+            ProtonError::DivisionByZero => write!(f, "cannot divide by zero"),
+            ProtonError::NoClosedForm(integrand) => write!(f, "no closed form found for the integral of {}", integrand),
+            ProtonError::RecursiveDefinition(name) => write!(f, "`{}` cannot be defined in terms of itself", name),
+            ProtonError::InvalidDefinition(message) => write!(f, "invalid definition: {}", message),
+            ProtonError::DimensionMismatch { expected, found } => {
+                write!(f, "expected {} components, found {}", expected, found)
+            }
+            ProtonError::NonFiniteValue { var, at } => write!(f, "the integrand is not finite at {} = {}", var, at),
+            ProtonError::NotConverged { value, error } => {
+                write!(f, "did not converge, last estimate {} with error {}", value, error)
+            }
+            ProtonError::Diverged { var, near } => write!(f, "the integral diverges near {} = {}", var, near),
+            ProtonError::InvalidBound(bound) => write!(f, "integration bound {} is not a number", bound),
+        }
+    }
+}
+
+impl std::error::Error for ProtonError {}
+
+impl From<ParseError> for ProtonError {
+    fn from(err: ParseError) -> Self {
+        ProtonError::Parse(err)
+    }
+}
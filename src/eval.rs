@@ -1,69 +1,115 @@
 use std::collections::HashMap;
 
-use crate::expr::Expr;
+use crate::error::ProtonError;
+use crate::expr::{variables, Expr};
+use crate::registry::{builtins, FunctionRegistry};
 
-pub fn evaluate(expr: &Expr, vars: &HashMap<String, f64>) -> f64 {
-    match expr {
-        Expr::Number(n) => *n, // just return the number itself
-        Expr::Variable(name) => { // Search for the variable in the map else panic
-            *vars.get(name)
-                .unwrap_or_else(|| {
-                    println!("Warning: variable {} not found", name);
-                    &f64::NAN
-                }) 
-        },
-        Expr::Add(left, right) => evaluate(left, vars) + evaluate(right, vars),
-        Expr::Sub(left, right) => evaluate(left, vars) - evaluate(right, vars),
-        Expr::Mul(left, right) => evaluate(left, vars) * evaluate(right, vars),
-        Expr::Div(left, right) => evaluate(left, vars) / evaluate(right, vars),
-        Expr::Pow(left, right) => evaluate(left, vars).powf(evaluate(right, vars)),
-        Expr::Func(name, args) => {
-            let values: Vec<f64> = args.iter().map(|arg| evaluate(arg, vars)).collect();
-            match name.as_str() {
-                "sin" => {
-                    if values.len() != 1 {
-                        panic!("sin() expects 1 argument");
-                    }
-                    values[0].sin()
-                },
-                "cos" => {
-                    if values.len() != 1 {
-                        panic!("cos() expects 1 argument");
-                    }
-                    values[0].cos()
-                },
-                "tan" => {
-                    if values.len() != 1 {
-                        panic!("tan() expects 1 argument");
-                    }
-                    values[0].tan()
-                },
-                "ln" => {
-                    if values.len() != 1 {
-                        panic!("ln() expects 1 argument");
-                    }
-                    values[0].ln()
-                },
-                "log10" => {
-                    if values.len() != 1 {
-                        panic!("log10() expects 1 argument");
-                    }
-                    values[0].log10()
-                },
-                "sqrt" => {
-                    if values.len() != 1 {
-                        panic!("sqrt() expects 1 argument");
-                    }
-                    values[0].sqrt()
-                },
-                "max" => values.iter().cloned()
-                    .fold(std::f64::NEG_INFINITY, f64::max),
-                
-                "min" => values.iter().cloned()
-                    .fold(std::f64::INFINITY, f64::min),
+// What to do when an expression uses a variable that has no value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnboundPolicy {
+    Error, // fail with every missing name
+    Nan, // evaluate the variable as NaN
+    Default(f64), // evaluate the variable as the given value
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EvalContext<'a> {
+    pub vars: &'a HashMap<String, f64>,
+    pub unbound: UnboundPolicy,
+    pub functions: &'a FunctionRegistry,
+}
+
+impl<'a> EvalContext<'a> {
+    // A strict context over the built-in functions: unbound variables are errors
+    pub fn new(vars: &'a HashMap<String, f64>) -> Self {
+        EvalContext {
+            vars,
+            unbound: UnboundPolicy::Error,
+            functions: builtins(),
+        }
+    }
+
+    pub fn with_policy(mut self, unbound: UnboundPolicy) -> Self {
+        self.unbound = unbound;
+        self
+    }
+
+    pub fn with_functions(mut self, functions: &'a FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn evaluate(&self, expr: &Expr) -> Result<f64, ProtonError> {
+        self.check_bound(expr)?;
+        self.evaluate_inner(expr)
+    }
 
-                _ => panic!("Unknown Function: {}", name),
+    // Under the strict policy, fail up front so the error names every missing
+    // variable, not just the first
+    pub(crate) fn check_bound(&self, expr: &Expr) -> Result<(), ProtonError> {
+        if self.unbound == UnboundPolicy::Error {
+            let missing: Vec<String> = variables(expr)
+                .into_iter()
+                .filter(|name| !self.vars.contains_key(name))
+                .collect();
+            if !missing.is_empty() {
+                return Err(ProtonError::UnboundVariables(missing));
             }
-        },
+        }
+        Ok(())
     }
+
+    // `expr` checked for unbound variables, with every defined function
+    // inlined: defined functions have no derivative rule of their own, so the
+    // autodiff and dual evaluators differentiate through their definitions
+    pub(crate) fn expand(&self, expr: &Expr) -> Result<Expr, ProtonError> {
+        self.check_bound(expr)?;
+        Ok(self.functions.expand(expr))
+    }
+
+    // The value of a variable, falling back on the unbound policy
+    pub(crate) fn variable(&self, name: &str) -> Result<f64, ProtonError> {
+        match (self.vars.get(name), self.unbound) {
+            (Some(value), _) => Ok(*value),
+            (None, UnboundPolicy::Default(value)) => Ok(value),
+            (None, UnboundPolicy::Nan) => Ok(f64::NAN),
+            (None, UnboundPolicy::Error) => Err(ProtonError::UnboundVariables(vec![name.to_string()])),
+        }
+    }
+
+    fn evaluate_inner(&self, expr: &Expr) -> Result<f64, ProtonError> {
+        let value = match expr {
+            Expr::Number(n) => *n, // just return the number itself
+            Expr::Variable(name) => self.variable(name)?, // Search for the variable in the map
+            Expr::Add(left, right) => self.evaluate_inner(left)? + self.evaluate_inner(right)?,
+            Expr::Sub(left, right) => self.evaluate_inner(left)? - self.evaluate_inner(right)?,
+            Expr::Mul(left, right) => self.evaluate_inner(left)? * self.evaluate_inner(right)?,
+            Expr::Div(left, right) => self.evaluate_inner(left)? / self.evaluate_inner(right)?,
+            Expr::Pow(left, right) => self.evaluate_inner(left)?.powf(self.evaluate_inner(right)?),
+            Expr::Func(name, args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| self.evaluate_inner(arg))
+                    .collect::<Result<Vec<f64>, ProtonError>>()?;
+                self.functions.call(name, &values)?
+            },
+        };
+        Ok(value)
+    }
+}
+
+// Unbound variables evaluate to NaN
+pub fn evaluate(expr: &Expr, vars: &HashMap<String, f64>) -> f64 {
+    EvalContext::new(vars)
+        .with_policy(UnboundPolicy::Nan)
+        .evaluate(expr)
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Like `evaluate`, but unknown functions, wrong argument counts and unbound
+// variables are reported as errors instead of panicking or producing NaN.
+// Arithmetic itself follows IEEE semantics: 1 / 0 is inf and 0 / 0 is NaN,
+// not `ProtonError::DivisionByZero`
+pub fn try_evaluate(expr: &Expr, vars: &HashMap<String, f64>) -> Result<f64, ProtonError> {
+    EvalContext::new(vars).evaluate(expr)
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 #[derive(Debug, Clone, PartialEq)]
 
 pub enum Expr {
@@ -21,10 +23,121 @@ pub fn to_string(expr: &Expr) -> String {
         Expr::Sub(left, right) => format!("({} - {})", to_string(left), to_string(right)),
         Expr::Mul(left, right) => format!("({} * {})", to_string(left), to_string(right)),
         Expr::Div(left, right) => format!("({} / {})", to_string(left), to_string(right)),
-        Expr::Pow(left, right) => format!("({} ^ {})", to_string(left), to_string(right)),
+        // A negative base is parenthesised, since -2 ^ 2 reads as -(2 ^ 2)
+        Expr::Pow(left, right) => match **left {
+            Expr::Number(n) if n.is_sign_negative() => format!("(({}) ^ {})", n, to_string(right)),
+            _ => format!("({} ^ {})", to_string(left), to_string(right)),
+        },
         Expr::Func(name, args) => {
             let arg_str: Vec<String> = args.iter().map(to_string).collect();
             format!("{}({})", name, arg_str.join(", "))
         }
     }
 }
+
+// LaTeX rendering => \frac{x + 2}{y}, only adding the parentheses precedence needs
+pub fn to_latex(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(n) => n.to_string(),
+        Expr::Variable(name) => {
+            if name.chars().count() == 1 {
+                name.clone()
+            } else {
+                format!("\\mathrm{{{}}}", name)
+            }
+        }
+        Expr::Add(left, right) => format!("{} + {}", to_latex(left), to_latex(right)),
+        Expr::Sub(left, right) => format!("{} - {}", to_latex(left), latex_operand(right, 2)),
+        Expr::Mul(left, right) => format!("{} \\cdot {}", latex_operand(left, 2), latex_operand(right, 2)),
+        Expr::Div(left, right) => format!("\\frac{{{}}}{{{}}}", to_latex(left), to_latex(right)),
+        Expr::Pow(base, exponent) => format!("{}^{{{}}}", latex_operand(base, 4), to_latex(exponent)),
+        Expr::Func(name, args) => {
+            let arg_str: Vec<String> = args.iter().map(to_latex).collect();
+            let arg_str = arg_str.join(", ");
+            match name.as_str() {
+                "sqrt" => format!("\\sqrt{{{}}}", arg_str),
+                "log10" => format!("\\log_{{10}}\\left({}\\right)", arg_str),
+                "log" if args.len() == 2 => {
+                    format!("\\log_{{{}}}\\left({}\\right)", to_latex(&args[0]), to_latex(&args[1]))
+                }
+                "abs" => format!("\\left|{}\\right|", arg_str),
+                "asin" | "acos" | "atan" => format!("\\arc{}\\left({}\\right)", &name[1..], arg_str),
+                "sin" | "cos" | "tan" | "sec" | "csc" | "cot" | "sinh" | "cosh" | "tanh" | "exp" | "ln" | "max"
+                | "min" => format!("\\{}\\left({}\\right)", name, arg_str),
+                _ => format!("\\operatorname{{{}}}\\left({}\\right)", name, arg_str),
+            }
+        }
+    }
+}
+
+// Wrap `expr` in parentheses when it binds looser than `precedence`
+// (1 = sum, 2 = product, 3 = fraction or power, 4 = atom)
+fn latex_operand(expr: &Expr, precedence: u8) -> String {
+    let own = match expr {
+        Expr::Add(..) | Expr::Sub(..) => 1,
+        Expr::Number(n) if n.is_sign_negative() => 1, // reads like a unary minus
+        Expr::Mul(..) => 2,
+        Expr::Div(..) | Expr::Pow(..) => 3,
+        Expr::Number(_) | Expr::Variable(_) | Expr::Func(..) => 4,
+    };
+    if own < precedence {
+        format!("\\left({}\\right)", to_latex(expr))
+    } else {
+        to_latex(expr)
+    }
+}
+
+// Names of the variables appearing in `expr`, sorted and without duplicates
+pub fn variables(expr: &Expr) -> Vec<String> {
+    fn collect(expr: &Expr, names: &mut Vec<String>) {
+        match expr {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => names.push(name.clone()),
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Pow(left, right) => {
+                collect(left, names);
+                collect(right, names);
+            }
+            Expr::Func(_, args) => args.iter().for_each(|arg| collect(arg, names)),
+        }
+    }
+
+    let mut names = Vec::new();
+    collect(expr, &mut names);
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Whether `var` appears anywhere in `expr`
+pub fn contains_variable(expr: &Expr, var: &str) -> bool {
+    match expr {
+        Expr::Number(_) => false,
+        Expr::Variable(name) => name == var,
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Div(left, right)
+        | Expr::Pow(left, right) => contains_variable(left, var) || contains_variable(right, var),
+        Expr::Func(_, args) => args.iter().any(|arg| contains_variable(arg, var)),
+    }
+}
+
+// Replace variables by expressions, all at once, so swapping works:
+// substitute(x - y, {x: y, y: x}) = y - x
+pub fn substitute(expr: &Expr, replacements: &HashMap<String, Expr>) -> Expr {
+    let sub = |e: &Expr| Box::new(substitute(e, replacements));
+    match expr {
+        Expr::Number(_) => expr.clone(),
+        Expr::Variable(name) => replacements.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        Expr::Add(left, right) => Expr::Add(sub(left), sub(right)),
+        Expr::Sub(left, right) => Expr::Sub(sub(left), sub(right)),
+        Expr::Mul(left, right) => Expr::Mul(sub(left), sub(right)),
+        Expr::Div(left, right) => Expr::Div(sub(left), sub(right)),
+        Expr::Pow(left, right) => Expr::Pow(sub(left), sub(right)),
+        Expr::Func(name, args) => Expr::Func(name.clone(), args.iter().map(|arg| substitute(arg, replacements)).collect()),
+    }
+}
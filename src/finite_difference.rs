@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use crate::error::ProtonError;
+use crate::eval::EvalContext;
+use crate::expr::Expr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Forward, // (f(x + h) - f(x)) / h, error O(h)
+    Central, // (f(x + h) - f(x - h)) / 2h, error O(h^2)
+    Richardson, // central differences at shrinking steps, extrapolated to h = 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub error: f64, // estimated absolute error of `value`
+    pub evaluations: usize,
+}
+
+// Richardson tableau: steps shrink by STEP_RATIO per row, up to MAX_ROWS rows,
+// and stop once the error grows by SAFE over the best seen so far
+const STEP_RATIO: f64 = 1.4;
+const MAX_ROWS: usize = 10;
+const SAFE: f64 = 2.0;
+
+// Evaluates `expr` with one variable moved away from the point
+struct Probe<'a, 'b> {
+    expr: &'b Expr,
+    context: &'b EvalContext<'a>,
+    vars: HashMap<String, f64>,
+    evaluations: usize,
+}
+
+impl Probe<'_, '_> {
+    fn at(&mut self, shifts: &[(&str, f64)]) -> Result<f64, ProtonError> {
+        for &(var, shift) in shifts {
+            *self.vars.get_mut(var).unwrap() += shift;
+        }
+        let value = EvalContext { vars: &self.vars, ..*self.context }.evaluate(self.expr);
+        for &(var, shift) in shifts {
+            *self.vars.get_mut(var).unwrap() -= shift;
+        }
+        self.evaluations += 1;
+        value
+    }
+
+    fn central(&mut self, var: &str, h: f64) -> Result<f64, ProtonError> {
+        Ok((self.at(&[(var, h)])? - self.at(&[(var, -h)])?) / (2.0 * h))
+    }
+
+    fn forward(&mut self, var: &str, h: f64) -> Result<f64, ProtonError> {
+        Ok((self.at(&[(var, h)])? - self.at(&[])?) / h)
+    }
+
+    // ∂²f/∂x∂y from the four corners of a square of side 2h
+    fn mixed(&mut self, x: &str, y: &str, h: f64) -> Result<f64, ProtonError> {
+        let corners = self.at(&[(x, h), (y, h)])? - self.at(&[(x, h), (y, -h)])? - self.at(&[(x, -h), (y, h)])?
+            + self.at(&[(x, -h), (y, -h)])?;
+        Ok(corners / (4.0 * h * h))
+    }
+}
+
+// Steps with the error of the scheme balanced against rounding, scaled to the point
+fn step(point: f64, exponent: f64) -> f64 {
+    f64::EPSILON.powf(exponent) * point.abs().max(1.0)
+}
+
+fn point_of(context: &EvalContext, var: &str) -> Result<f64, ProtonError> {
+    context
+        .vars
+        .get(var)
+        .copied()
+        .ok_or_else(|| ProtonError::UnboundVariables(vec![var.to_string()]))
+}
+
+// ∂expr/∂var at the point given by `vars`, holding every other variable fixed
+pub fn derivative(expr: &Expr, var: &str, vars: &HashMap<String, f64>, scheme: Scheme) -> Result<Estimate, ProtonError> {
+    derivative_with(expr, var, &EvalContext::new(vars), scheme)
+}
+
+// Like `derivative`, evaluating with the unbound policy and function table of `context`
+pub fn derivative_with(expr: &Expr, var: &str, context: &EvalContext, scheme: Scheme) -> Result<Estimate, ProtonError> {
+    let x = point_of(context, var)?;
+    let mut probe = Probe {
+        expr,
+        context,
+        vars: context.vars.clone(),
+        evaluations: 0,
+    };
+
+    let (value, error) = match scheme {
+        // The difference between steps h and 2h estimates the truncation error
+        Scheme::Forward => {
+            let h = step(x, 0.5);
+            let value = probe.forward(var, h)?;
+            (value, (value - probe.forward(var, 2.0 * h)?).abs())
+        }
+        Scheme::Central => {
+            let h = step(x, 1.0 / 3.0);
+            let value = probe.central(var, h)?;
+            (value, (value - probe.central(var, 2.0 * h)?).abs() / 3.0)
+        }
+        Scheme::Richardson => richardson(&mut probe, var, 0.1 * x.abs().max(1.0))?,
+    };
+
+    Ok(Estimate {
+        value,
+        error,
+        evaluations: probe.evaluations,
+    })
+}
+
+// Ridders' method: each row halves the step by STEP_RATIO and every column
+// removes one more even power of h from the error
+fn richardson(probe: &mut Probe, var: &str, mut h: f64) -> Result<(f64, f64), ProtonError> {
+    let ratio2 = STEP_RATIO * STEP_RATIO;
+    let mut previous = vec![probe.central(var, h)?];
+    let (mut best, mut error) = (previous[0], f64::INFINITY);
+
+    for _ in 1..MAX_ROWS {
+        h /= STEP_RATIO;
+        let mut row = vec![probe.central(var, h)?];
+        let mut factor = ratio2;
+        for j in 1..=previous.len() {
+            let extrapolated = (row[j - 1] * factor - previous[j - 1]) / (factor - 1.0);
+            factor *= ratio2;
+            let change = (extrapolated - row[j - 1]).abs().max((extrapolated - previous[j - 1]).abs());
+            if change <= error {
+                error = change;
+                best = extrapolated;
+            }
+            row.push(extrapolated);
+        }
+        // Higher orders stopped helping, rounding has taken over
+        let last = row.len() - 1;
+        if (row[last] - previous[last - 1]).abs() >= SAFE * error {
+            break;
+        }
+        previous = row;
+    }
+    Ok((best, error))
+}
+
+// ∂²expr/∂x∂y at `vars`, or ∂²expr/∂x² when `x` and `y` are the same variable
+pub fn second_derivative(expr: &Expr, x: &str, y: &str, vars: &HashMap<String, f64>) -> Result<Estimate, ProtonError> {
+    second_derivative_with(expr, x, y, &EvalContext::new(vars))
+}
+
+pub fn second_derivative_with(expr: &Expr, x: &str, y: &str, context: &EvalContext) -> Result<Estimate, ProtonError> {
+    let scale = point_of(context, x)?.abs().max(point_of(context, y)?.abs());
+    let h = step(scale, 0.25);
+    let mut probe = Probe {
+        expr,
+        context,
+        vars: context.vars.clone(),
+        evaluations: 0,
+    };
+
+    let mut at_step = |h: f64| -> Result<f64, ProtonError> {
+        if x == y {
+            let center = probe.at(&[])?;
+            Ok((probe.at(&[(x, h)])? - 2.0 * center + probe.at(&[(x, -h)])?) / (h * h))
+        } else {
+            probe.mixed(x, y, h)
+        }
+    };
+    let value = at_step(h)?;
+    let error = (value - at_step(2.0 * h)?).abs() / 3.0;
+
+    Ok(Estimate {
+        value,
+        error,
+        evaluations: probe.evaluations,
+    })
+}
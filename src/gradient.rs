@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::differentiate::try_partial_with;
+use crate::error::ProtonError;
+use crate::eval::{evaluate, try_evaluate};
+use crate::expr::Expr;
+use crate::registry::{builtins, FunctionRegistry};
+
+// [∂expr/∂v for v in vars], every entry simplified
+pub fn gradient(expr: &Expr, vars: &[&str]) -> Vec<Expr> {
+    try_gradient(expr, vars).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_gradient(expr: &Expr, vars: &[&str]) -> Result<Vec<Expr>, ProtonError> {
+    try_gradient_with(expr, vars, builtins())
+}
+
+pub fn try_gradient_with(expr: &Expr, vars: &[&str], functions: &FunctionRegistry) -> Result<Vec<Expr>, ProtonError> {
+    vars.iter()
+        .map(|var| try_partial_with(expr, &[(var, 1)], functions))
+        .collect()
+}
+
+// One row per expression, one column per variable: J[i][j] = ∂exprs[i]/∂vars[j]
+pub fn jacobian(exprs: &[Expr], vars: &[&str]) -> Vec<Vec<Expr>> {
+    try_jacobian(exprs, vars).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_jacobian(exprs: &[Expr], vars: &[&str]) -> Result<Vec<Vec<Expr>>, ProtonError> {
+    try_jacobian_with(exprs, vars, builtins())
+}
+
+pub fn try_jacobian_with(exprs: &[Expr], vars: &[&str], functions: &FunctionRegistry) -> Result<Vec<Vec<Expr>>, ProtonError> {
+    exprs
+        .iter()
+        .map(|expr| try_gradient_with(expr, vars, functions))
+        .collect()
+}
+
+// H[i][j] = ∂²expr/∂vars[i]∂vars[j]. Only the upper triangle is differentiated,
+// the lower one is mirrored from it since mixed partials commute.
+pub fn hessian(expr: &Expr, vars: &[&str]) -> Vec<Vec<Expr>> {
+    try_hessian(expr, vars).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_hessian(expr: &Expr, vars: &[&str]) -> Result<Vec<Vec<Expr>>, ProtonError> {
+    try_hessian_with(expr, vars, builtins())
+}
+
+pub fn try_hessian_with(expr: &Expr, vars: &[&str], functions: &FunctionRegistry) -> Result<Vec<Vec<Expr>>, ProtonError> {
+    let gradient = try_gradient_with(expr, vars, functions)?;
+    let mut hessian = vec![vec![Expr::Number(0.0); vars.len()]; vars.len()];
+    for i in 0..vars.len() {
+        for j in i..vars.len() {
+            let entry = try_partial_with(&gradient[i], &[(vars[j], 1)], functions)?;
+            hessian[j][i] = entry.clone();
+            hessian[i][j] = entry;
+        }
+    }
+    Ok(hessian)
+}
+
+// Evaluate every entry of a symbolic matrix at `point`, unbound variables give NaN
+pub fn evaluate_matrix(matrix: &[Vec<Expr>], point: &HashMap<String, f64>) -> Vec<Vec<f64>> {
+    matrix
+        .iter()
+        .map(|row| row.iter().map(|entry| evaluate(entry, point)).collect())
+        .collect()
+}
+
+// Like `evaluate_matrix`, but stops at the first entry that cannot be evaluated
+pub fn try_evaluate_matrix(matrix: &[Vec<Expr>], point: &HashMap<String, f64>) -> Result<Vec<Vec<f64>>, ProtonError> {
+    matrix
+        .iter()
+        .map(|row| row.iter().map(|entry| try_evaluate(entry, point)).collect())
+        .collect()
+}
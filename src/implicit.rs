@@ -0,0 +1,60 @@
+use crate::differentiate::try_partial_with;
+use crate::error::ProtonError;
+use crate::expr::Expr;
+use crate::registry::{builtins, FunctionRegistry};
+use crate::simplify::try_simplify_with;
+
+// dy/dx on the curve `equation = 0`, i.e. -F_x / F_y, in terms of x and y
+pub fn implicit_derivative(equation: &Expr, x: &str, y: &str) -> Expr {
+    try_implicit_derivative(equation, x, y).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_implicit_derivative(equation: &Expr, x: &str, y: &str) -> Result<Expr, ProtonError> {
+    try_implicit_derivative_with(equation, x, y, builtins())
+}
+
+pub fn try_implicit_derivative_with(equation: &Expr, x: &str, y: &str, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    try_implicit_derivative_n_with(equation, x, y, 1, functions)
+}
+
+// The n-th derivative of y with respect to x on the curve `equation = 0`
+pub fn implicit_derivative_n(equation: &Expr, x: &str, y: &str, n: usize) -> Expr {
+    try_implicit_derivative_n(equation, x, y, n).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_implicit_derivative_n(equation: &Expr, x: &str, y: &str, n: usize) -> Result<Expr, ProtonError> {
+    try_implicit_derivative_n_with(equation, x, y, n, builtins())
+}
+
+// F_y = 0 identically (y does not appear) is reported as a division by zero
+pub fn try_implicit_derivative_n_with(
+    equation: &Expr,
+    x: &str,
+    y: &str,
+    n: usize,
+    functions: &FunctionRegistry,
+) -> Result<Expr, ProtonError> {
+    if n == 0 {
+        return Ok(Expr::Variable(y.to_string()));
+    }
+
+    let f_x = try_partial_with(equation, &[(x, 1)], functions)?;
+    let f_y = try_partial_with(equation, &[(y, 1)], functions)?;
+    let dy = try_simplify_with(
+        &Expr::Mul(Box::new(Expr::Number(-1.0)), Box::new(Expr::Div(Box::new(f_x), Box::new(f_y)))),
+        functions,
+    )?;
+
+    // Each further order is the total derivative of the last one, with y a
+    // function of x: d/dx g(x, y) = g_x + g_y * y'
+    let mut derivative = dy.clone();
+    for _ in 1..n {
+        let g_x = try_partial_with(&derivative, &[(x, 1)], functions)?;
+        let g_y = try_partial_with(&derivative, &[(y, 1)], functions)?;
+        derivative = try_simplify_with(
+            &Expr::Add(Box::new(g_x), Box::new(Expr::Mul(Box::new(g_y), Box::new(dy.clone())))),
+            functions,
+        )?;
+    }
+    Ok(derivative)
+}
@@ -1,7 +1,41 @@
-use crate::expr::{Expr};
+use crate::differentiate::try_differentiate_with;
+use crate::error::ProtonError;
+use crate::expr::{contains_variable, to_string, variables, Expr};
+use crate::partial_fractions::integrate_rational;
+use crate::registry::{builtins, FunctionRegistry};
+use crate::simplify::try_simplify_with;
+
+// Integration by parts is repeated at most this many times on one product,
+// enough for x^4 * exp(x) or the two rounds of exp(x) * sin(x)
+const MAX_PARTS_STEPS: usize = 6;
 
 pub fn integrate(expr: &Expr, var: &str) -> Expr {
-    match expr {
+    try_integrate(expr, var).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Like `integrate`, but integrands without a known antiderivative are reported as errors
+pub fn try_integrate(expr: &Expr, var: &str) -> Result<Expr, ProtonError> {
+    try_integrate_with(expr, var, builtins())
+}
+
+// Integrate using the antiderivatives registered in `functions`
+pub fn try_integrate_with(expr: &Expr, var: &str, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    integrate_inner(expr, var, functions, true)
+}
+
+// `parts` is false while already integrating by parts, so that the steps of
+// one product never start a nested round of their own
+fn integrate_inner(expr: &Expr, var: &str, functions: &FunctionRegistry, parts: bool) -> Result<Expr, ProtonError> {
+    let no_closed_form = || ProtonError::NoClosedForm(to_string(expr));
+
+    // User-defined functions are integrated through their definition
+    if let Expr::Func(name, args) = expr
+        && let Some(body) = functions.instantiate(name, args)
+    {
+        return integrate_inner(&body, var, functions, parts);
+    }
+
+    let integral = match expr {
         // ∫c dx = cx + k
         Expr::Number(c) => {
             Expr::Mul(
@@ -10,6 +44,14 @@ pub fn integrate(expr: &Expr, var: &str) -> Expr {
             )
         },
 
+        // Anything else without the variable is a constant too
+        c if !contains_variable(c, var) => {
+            Expr::Mul(
+                Box::new(c.clone()),
+                Box::new(Expr::Variable(var.to_string()))
+            )
+        },
+
         Expr::Variable(x) if x == var => {
             // ∫x dx = x^2 / 2
             Expr::Div(
@@ -23,16 +65,16 @@ pub fn integrate(expr: &Expr, var: &str) -> Expr {
         // ∫(f + g) = ∫f + ∫g
         Expr::Add(left, right) => {
             Expr::Add(
-                Box::new(integrate(&left, var)),
-                Box::new(integrate(&right, var))
+                Box::new(integrate_inner(left, var, functions, parts)?),
+                Box::new(integrate_inner(right, var, functions, parts)?)
             )
         },
 
         // ∫(f - g) = ∫f - ∫g
         Expr::Sub(left, right) => {
             Expr::Sub(
-                Box::new(integrate(&left, var)),
-                Box::new(integrate(&right, var))
+                Box::new(integrate_inner(left, var, functions, parts)?),
+                Box::new(integrate_inner(right, var, functions, parts)?)
             )
         },
         
@@ -40,70 +82,367 @@ pub fn integrate(expr: &Expr, var: &str) -> Expr {
         Expr::Mul(left, right) => {
             match (&** left, &** right) {
                 // ∫kf = k∫f
-                (Expr::Number(c), f) | (f, Expr::Number(c)) => {
+                (c, f) | (f, c) if !contains_variable(c, var) => {
                     Expr::Mul(
-                        Box::new(Expr::Number(*c)),
-                        Box::new(integrate(&f, var)),
+                        Box::new(c.clone()),
+                        Box::new(integrate_inner(f, var, functions, parts)?),
                     )
                 },
-                _ => panic!("Yet to implement Integration by Parts"),
-                // TODO: Add integration by parts     
+                _ => match monomial(expr, var) {
+                    Some((c, n)) => integrate_monomial(c, n, var),
+                    None => match substitute_and_integrate(expr, var, functions, parts)
+                        .or_else(|| partial_fractions(expr, var, functions))
+                    {
+                        Some(integral) => integral,
+                        None if parts => by_parts(expr, var, functions)?,
+                        None => return Err(no_closed_form()),
+                    },
+                },
             }
         }
 
-        Expr::Pow(base, exponent) if matches!(**base, Expr::Variable(ref v) if v == var) => {
-            if let Expr::Number(n) = &**exponent {
-                // ∫1/x = ln(x)
-                if *n == -1.0 {
-                    Expr::Func(
-                        "ln".to_string(),
-                        vec![Expr::Variable(var.to_string())],
-                    )
-                } else {
-                    // ∫x^n = x^(n + 1) / (n + 1)
-                    Expr::Div(
-                        Box::new(
-                            Expr::Pow(
-                                Box::new(Expr::Variable(var.to_string())),
-                                Box::new(Expr::Number(n + 1.0))
-                            )
-                        ),
-                        Box::new(Expr::Number(n + 1.0))
-                    )
-                }
+        // ∫f/k = ∫f / k
+        Expr::Div(left, right) if !contains_variable(right, var) => {
+            Expr::Div(
+                Box::new(integrate_inner(left, var, functions, parts)?),
+                right.clone(),
+            )
+        }
+
+        // Quotients like x^3 / (2 * x)
+        Expr::Div(..) if let Some((c, n)) = monomial(expr, var) => integrate_monomial(c, n, var),
+
+        Expr::Pow(base, exponent) if let (Expr::Variable(v), Expr::Number(n)) = (&**base, &**exponent) && v == var => {
+            let n = *n;
+            // ∫1/x = ln|x|
+            if n == -1.0 {
+                ln_abs(Expr::Variable(var.to_string()))
             } else {
-                panic!("Non-numeric exponent not supported yet")
+                // ∫x^n = x^(n + 1) / (n + 1)
+                Expr::Div(
+                    Box::new(
+                        Expr::Pow(
+                            Box::new(Expr::Variable(var.to_string())),
+                            Box::new(Expr::Number(n + 1.0))
+                        )
+                    ),
+                    Box::new(Expr::Number(n + 1.0))
+                )
             }
         }
 
-        Expr::Func(name, args) if args.len() == 1 => {
-            let arg = &args[0];
-            match name.as_str() {
-                // ∫sin(x) dx = -cos(x)
-                "sin" => Expr::Mul(
-                    Box::new(Expr::Number(-1.0)),
-                    Box::new(Expr::Func("cos".into(), vec![arg.clone()]))
-                ),
+        // ∫a^x = a^x / ln(a)
+        Expr::Pow(base, exponent) if !contains_variable(base, var) && matches!(**exponent, Expr::Variable(ref v) if v == var) => {
+            Expr::Div(
+                Box::new(expr.clone()),
+                Box::new(Expr::Func("ln".to_string(), vec![(**base).clone()])),
+            )
+        }
 
-                // ∫cos(x) dx = sin(x)
-                "cos" => Expr::Func(
-                    "sin".to_string(),
-                    vec![arg.clone()]
-                ),
+        // ∫f(x) = F(x) from the function's registered antiderivative; other
+        // arguments go through substitution below
+        Expr::Func(name, args) if args.len() == 1 && args[0] == Expr::Variable(var.to_string()) => {
+            let function = functions.lookup(name)?;
+            match &function.antiderivative {
+                Some(rule) => rule(&args[0]),
+                // ∫atan(x) = x * atan(x) - ∫x / (1 + x^2), and likewise for logarithms
+                None if parts && liate(expr, var) >= Liate::InverseTrigonometric => by_parts(expr, var, functions)?,
+                None => {
+                    return Err(ProtonError::UnsupportedFunction {
+                        name: name.clone(),
+                        operation: "integrate",
+                    })
+                }
+            }
+        }
 
-                // ∫ln(x) dx = xln(x) - x
-                "ln" => {
-                    Expr::Sub(
-                        Box::new(Expr::Mul(Box::new(arg.clone()), Box::new(Expr::Func("ln".into(), vec![arg.clone()])))),
-                        Box::new(arg.clone())
-                    )
-                },
+        // Quotients of polynomials that substitution can't reduce, like
+        // 1 / (x^2 - 1), go through partial fractions
+        _ => match substitute_and_integrate(expr, var, functions, parts).or_else(|| partial_fractions(expr, var, functions)) {
+            Some(integral) => integral,
+            // Powers of a logarithm or inverse function, like ln(x)^2, by parts against dv = 1
+            None if parts && liate(expr, var) >= Liate::InverseTrigonometric => by_parts(expr, var, functions)?,
+            // Say why a call failed when the function itself is the problem
+            None => {
+                if let Expr::Func(name, _) = expr
+                    && functions.lookup(name)?.antiderivative.is_none()
+                {
+                    return Err(ProtonError::UnsupportedFunction {
+                        name: name.clone(),
+                        operation: "integrate",
+                    });
+                }
+                return Err(no_closed_form());
+            }
+        },
+    };
+    Ok(integral)
+}
+
+// Split c * x^n, with c free of the variable, into (c, n)
+fn monomial(expr: &Expr, var: &str) -> Option<(Expr, f64)> {
+    match expr {
+        c if !contains_variable(c, var) => Some((c.clone(), 0.0)),
+        Expr::Variable(_) => Some((Expr::Number(1.0), 1.0)),
+        Expr::Pow(base, exponent) => match (&**base, &**exponent) {
+            (Expr::Variable(_), Expr::Number(n)) => Some((Expr::Number(1.0), *n)),
+            _ => None,
+        },
+        Expr::Mul(left, right) => {
+            let ((a, m), (b, n)) = (monomial(left, var)?, monomial(right, var)?);
+            Some((Expr::Mul(Box::new(a), Box::new(b)), m + n))
+        }
+        Expr::Div(left, right) => {
+            let ((a, m), (b, n)) = (monomial(left, var)?, monomial(right, var)?);
+            Some((Expr::Div(Box::new(a), Box::new(b)), m - n))
+        }
+        _ => None,
+    }
+}
+
+// ∫c * x^n = c * x^(n + 1) / (n + 1), or c * ln|x| for n = -1
+fn integrate_monomial(c: Expr, n: f64, var: &str) -> Expr {
+    let x = Expr::Variable(var.to_string());
+    let integral = if n == -1.0 {
+        ln_abs(x)
+    } else {
+        Expr::Div(
+            Box::new(Expr::Pow(Box::new(x), Box::new(Expr::Number(n + 1.0)))),
+            Box::new(Expr::Number(n + 1.0)),
+        )
+    };
+    Expr::Mul(Box::new(c), Box::new(integral))
+}
 
-                _ => panic!("No support for this function yet!")
+// ln|u|, the antiderivative of 1/u on either side of 0; substitution relies
+// on it too, so ∫1/(2x + 1) holds for x < -1/2 like partial fractions do
+fn ln_abs(u: Expr) -> Expr {
+    Expr::Func("ln".to_string(), vec![Expr::Func("abs".to_string(), vec![u])])
+}
+
+// Which factor of a product to differentiate when integrating by parts, by
+// LIATE: logarithms first, then inverse trigonometric, algebraic,
+// trigonometric and finally exponential factors, which are kept to integrate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Liate {
+    Other,
+    Exponential,
+    Trigonometric,
+    Algebraic,
+    InverseTrigonometric,
+    Logarithmic,
+}
+
+fn liate(factor: &Expr, var: &str) -> Liate {
+    match factor {
+        Expr::Func(name, _) => match name.as_str() {
+            "ln" | "log" | "log10" => Liate::Logarithmic,
+            "asin" | "acos" | "atan" => Liate::InverseTrigonometric,
+            "sin" | "cos" | "tan" | "sec" | "csc" | "cot" => Liate::Trigonometric,
+            "exp" => Liate::Exponential,
+            _ => Liate::Other,
+        },
+        // a^x
+        Expr::Pow(base, _) if !contains_variable(base, var) => Liate::Exponential,
+        // f^n ranks like f, so ln(x)^2 is still logarithmic
+        Expr::Pow(base, exponent) if !contains_variable(exponent, var) => liate(base, var),
+        _ if monomial(factor, var).is_some() => Liate::Algebraic,
+        _ => Liate::Other,
+    }
+}
+
+// A product, or quotient, as a numeric coefficient times its other factors, sorted so that
+// products which differ only in order or coefficient compare equal
+fn factors(expr: &Expr) -> (f64, Vec<Expr>) {
+    fn collect(expr: &Expr, coefficient: &mut f64, factors: &mut Vec<Expr>) {
+        match expr {
+            Expr::Number(n) => *coefficient *= n,
+            Expr::Mul(left, right) => {
+                collect(left, coefficient, factors);
+                collect(right, coefficient, factors);
+            }
+            Expr::Div(left, right) => {
+                collect(left, coefficient, factors);
+                match &**right {
+                    Expr::Number(n) => *coefficient /= n,
+                    _ => factors.push(Expr::Pow(right.clone(), Box::new(Expr::Number(-1.0)))),
+                }
             }
+            _ => factors.push(expr.clone()),
         }
+    }
+    let mut coefficient = 1.0;
+    let mut list = Vec::new();
+    collect(expr, &mut coefficient, &mut list);
+    list.sort_by_key(to_string);
+    (coefficient, list)
+}
+
+fn product(factors: &[Expr]) -> Expr {
+    factors
+        .iter()
+        .cloned()
+        .reduce(|product, factor| Expr::Mul(Box::new(product), Box::new(factor)))
+        .unwrap_or(Expr::Number(1.0))
+}
+
+// ∫u dv = u v - ∫v du, repeated while the remaining integral is still a
+// product. Keeps ∫expr = done + k * ∫remaining, so when the remaining integral
+// comes back around to c * ∫expr, as for exp(x) * sin(x), the equation
+// ∫expr = done + k c ∫expr is solved for ∫expr instead.
+fn by_parts(expr: &Expr, var: &str, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    let no_closed_form = || ProtonError::NoClosedForm(to_string(expr));
+    // A step that fails names its own piece; report the product we started with
+    let step_failed = |err| match err {
+        ProtonError::NoClosedForm(_) => no_closed_form(),
+        err => err,
+    };
+    let (original_coefficient, original) = factors(expr);
+
+    let mut done = Expr::Number(0.0);
+    let mut k = 1.0;
+    let mut remaining = expr.clone();
+
+    for step in 0..MAX_PARTS_STEPS {
+        let (coefficient, list) = factors(&remaining);
+        if step > 0 && list == original {
+            let c = coefficient / original_coefficient;
+            if k * c == 1.0 {
+                return Err(no_closed_form());
+            }
+            let solved = Expr::Div(Box::new(done), Box::new(Expr::Number(1.0 - k * c)));
+            return try_simplify_with(&solved, functions);
+        }
+        if step > 0 && let Ok(integral) = integrate_inner(&remaining, var, functions, false) {
+            let total = Expr::Add(
+                Box::new(done),
+                Box::new(Expr::Mul(Box::new(Expr::Number(k)), Box::new(integral))),
+            );
+            return try_simplify_with(&total, functions);
+        }
+
+        // u gets the factors of the highest LIATE class, dv the rest; a lone
+        // logarithm or inverse function is integrated against dv = 1
+        let best = list.iter().map(|factor| liate(factor, var)).max().ok_or_else(no_closed_form)?;
+        let (u, dv): (Vec<Expr>, Vec<Expr>) = list.into_iter().partition(|factor| liate(factor, var) == best);
+        if best == Liate::Other || (dv.is_empty() && best < Liate::InverseTrigonometric) {
+            return Err(no_closed_form());
+        }
+        let (u, dv) = (product(&u), product(&dv));
+
+        let v = try_simplify_with(&integrate_inner(&dv, var, functions, false).map_err(step_failed)?, functions)?;
+        let du = try_differentiate_with(&u, var, functions)?;
+        let uv = Expr::Mul(Box::new(u), Box::new(v.clone()));
+        done = Expr::Add(
+            Box::new(done),
+            Box::new(Expr::Mul(Box::new(Expr::Number(k * coefficient)), Box::new(uv))),
+        );
+        k *= -coefficient;
+        remaining = try_simplify_with(&Expr::Mul(Box::new(v), Box::new(du)), functions)?;
+    }
+    Err(no_closed_form())
+}
 
-        _ => panic!("No support yet")
+// Replace every occurrence of the subtree `target` by `replacement`
+fn replace(expr: &Expr, target: &Expr, replacement: &Expr) -> Expr {
+    if expr == target {
+        return replacement.clone();
+    }
+    let rep = |e: &Expr| Box::new(replace(e, target, replacement));
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => expr.clone(),
+        Expr::Add(left, right) => Expr::Add(rep(left), rep(right)),
+        Expr::Sub(left, right) => Expr::Sub(rep(left), rep(right)),
+        Expr::Mul(left, right) => Expr::Mul(rep(left), rep(right)),
+        Expr::Div(left, right) => Expr::Div(rep(left), rep(right)),
+        Expr::Pow(left, right) => Expr::Pow(rep(left), rep(right)),
+        Expr::Func(name, args) => Expr::Func(name.clone(), args.iter().map(|arg| replace(arg, target, replacement)).collect()),
+    }
+}
+
+// Every subtree that depends on the variable, other than the variable itself,
+// as candidates for the inner function g of a substitution
+fn inner_candidates(expr: &Expr, var: &str, candidates: &mut Vec<Expr>) {
+    if !contains_variable(expr, var) || *expr == Expr::Variable(var.to_string()) {
+        return;
+    }
+    if !candidates.contains(expr) {
+        candidates.push(expr.clone());
+    }
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => {}
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Div(left, right)
+        | Expr::Pow(left, right) => {
+            inner_candidates(left, var, candidates);
+            inner_candidates(right, var, candidates);
+        }
+        Expr::Func(_, args) => args.iter().for_each(|arg| inner_candidates(arg, var, candidates)),
+    }
+}
+
+// Rational functions by partial fractions, tidied like the other strategies
+fn partial_fractions(expr: &Expr, var: &str, functions: &FunctionRegistry) -> Option<Expr> {
+    try_simplify_with(&integrate_rational(expr, var)?, functions).ok()
+}
+
+// ∫f(g(x)) * g'(x) dx = ∫f(u) du with u = g(x). Tries every g in the
+// integrand: the factors of g' that depend on x must all appear among the
+// integrand's factors, and once they are divided out and g is replaced by u,
+// x must be gone. A constant g', as for sin(2 * x + 1), divides the result.
+fn substitute_and_integrate(expr: &Expr, var: &str, functions: &FunctionRegistry, parts: bool) -> Option<Expr> {
+    let (coefficient, integrand) = factors(expr);
+    let mut candidates = Vec::new();
+    inner_candidates(expr, var, &mut candidates);
+
+    // A name for u that cannot clash with the integrand's own variables
+    let names = variables(expr);
+    let u = (0..).map(|i| format!("u{}", i)).find(|name| !names.contains(name))?;
+    let u_var = Expr::Variable(u.clone());
+
+    for g in candidates {
+        let Ok(dg) = try_differentiate_with(&g, var, functions).and_then(|d| try_simplify_with(&d, functions)) else {
+            continue;
+        };
+        let (dg_coefficient, dg_factors) = factors(&dg);
+        if dg_coefficient == 0.0 {
+            continue;
+        }
+
+        // Divide g' out of the integrand, keeping its constant factors aside
+        let mut remaining = integrand.clone();
+        let mut constant = Vec::new();
+        let mut divides = true;
+        for factor in dg_factors {
+            if !contains_variable(&factor, var) {
+                constant.push(factor);
+            } else if let Some(i) = remaining.iter().position(|f| *f == factor) {
+                remaining.remove(i);
+            } else {
+                divides = false;
+                break;
+            }
+        }
+        if !divides {
+            continue;
+        }
+
+        let in_u = replace(&product(&remaining), &g, &u_var);
+        if contains_variable(&in_u, var) {
+            continue;
+        }
+        let Ok(integral) = integrate_inner(&in_u, &u, functions, parts) else {
+            continue;
+        };
 
+        let scale = Expr::Div(
+            Box::new(Expr::Number(coefficient / dg_coefficient)),
+            Box::new(product(&constant)),
+        );
+        let result = Expr::Mul(Box::new(scale), Box::new(replace(&integral, &u_var, &g)));
+        return try_simplify_with(&result, functions).ok();
     }
+    None
 }
@@ -1,5 +1,19 @@
 pub mod expr;
+pub mod error;
 pub mod eval;
+pub mod registry;
 pub mod integrate;
+pub mod definite;
+mod partial_fractions;
 pub mod differentiate;
+pub mod gradient;
+pub mod autodiff;
+pub mod dual;
+pub mod finite_difference;
+pub mod quadrature;
+pub mod implicit;
+pub mod vector;
 pub mod simplify;
+pub mod parse;
+pub mod repl;
+pub mod batch;
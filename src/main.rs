@@ -1,4 +1,267 @@
-fn main() {
-    // Contents have been moved to examples/example.rs
-    // This file is kept in case of using this engine as a CLI or an API service
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::ExitCode;
+
+use proton_lite::batch::evaluate_csv;
+use proton_lite::differentiate::try_differentiate;
+use proton_lite::error::ProtonError;
+use proton_lite::eval::try_evaluate;
+use proton_lite::expr::{to_latex, to_string, Expr};
+use proton_lite::integrate::try_integrate;
+use proton_lite::parse::parse;
+use proton_lite::repl::repl;
+use proton_lite::simplify::try_simplify;
+
+const USAGE: &str = "\
+usage: proton <command> [options]
+
+commands:
+  eval <expr> [--var name=value]...   evaluate an expression
+  diff <expr> <var>                   differentiate with respect to <var>
+  integrate <expr> <var>              integrate with respect to <var>
+  simplify <expr>                     simplify an expression
+  batch <expr> <file.csv>             evaluate for every row of a CSV whose header
+                                      names the variables (`-` reads stdin)
+  repl                                start the interactive calculator
+
+options:
+  --format plain|latex|json           output format (default: plain)
+  --var name=value                    bind a variable for eval, may be repeated
+  --output <file.csv>                 where batch writes its CSV (default: stdout)
+  --column <name>                     name of the batch result column (default: result)";
+
+enum Format {
+    Plain,
+    Latex,
+    Json,
+}
+
+enum CliError {
+    Usage(String), // bad command line, exit code 2
+    Failed(String), // the expression could not be parsed or evaluated, exit code 1
+}
+
+enum Output {
+    Value(f64),
+    Expression(Expr),
+    Written, // batch output already went to its destination
+}
+
+struct Options {
+    format: Format,
+    vars: HashMap<String, f64>,
+    output: Option<String>,
+    column: String,
+    positional: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, CliError> {
+    let mut options = Options {
+        format: Format::Plain,
+        vars: HashMap::new(),
+        output: None,
+        column: "result".to_string(),
+        positional: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| CliError::Usage(format!("missing value for `{}`", flag)))
+        };
+
+        match flag {
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "plain" => Format::Plain,
+                    "latex" => Format::Latex,
+                    "json" => Format::Json,
+                    other => return Err(CliError::Usage(format!("unknown format `{}`", other))),
+                }
+            }
+            "--var" => {
+                let binding = value()?;
+                let (name, number) = binding
+                    .split_once('=')
+                    .ok_or_else(|| CliError::Usage(format!("expected `name=value`, found `{}`", binding)))?;
+                let number: f64 = number
+                    .trim()
+                    .parse()
+                    .map_err(|_| CliError::Usage(format!("`{}` is not a number", number)))?;
+                options.vars.insert(name.trim().to_string(), number);
+            }
+            "--output" => options.output = Some(value()?),
+            "--column" => options.column = value()?,
+            _ if flag.starts_with("--") => return Err(CliError::Usage(format!("unknown option `{}`", flag))),
+            _ => options.positional.push(arg.clone()),
+        }
+    }
+
+    Ok(options)
+}
+
+impl From<ProtonError> for CliError {
+    fn from(err: ProtonError) -> Self {
+        CliError::Failed(format!("error: {}\n", err))
+    }
+}
+
+fn parse_expr(source: &str) -> Result<Expr, CliError> {
+    parse(source).map_err(|err| CliError::Failed(err.render(source)))
+}
+
+fn expect_args<'a>(positional: &'a [String], names: &[&str]) -> Result<&'a [String], CliError> {
+    if positional.len() != names.len() + 1 {
+        let expected: Vec<String> = names.iter().map(|name| format!("<{}>", name)).collect();
+        return Err(CliError::Usage(format!("usage: proton {} {}", positional[0], expected.join(" "))));
+    }
+    Ok(&positional[1..])
+}
+
+fn run(options: &Options) -> Result<Output, CliError> {
+    let positional = &options.positional;
+    let command = positional.first().ok_or_else(|| CliError::Usage(USAGE.to_string()))?;
+
+    match command.as_str() {
+        "eval" => {
+            let args = expect_args(positional, &["expr"])?;
+            let expr = parse_expr(&args[0])?;
+            match try_evaluate(&expr, &options.vars) {
+                // Failed like a NaN row in `batch`; infinities are results
+                Ok(value) if value.is_nan() => Err(CliError::Failed("error: result is not a number\n".to_string())),
+                Ok(value) => Ok(Output::Value(value)),
+                Err(err @ ProtonError::UnboundVariables(_)) => Err(CliError::Failed(format!(
+                    "error: {}, bind them with --var name=value\n",
+                    err
+                ))),
+                Err(err) => Err(err.into()),
+            }
+        }
+        "diff" => {
+            let args = expect_args(positional, &["expr", "var"])?;
+            let expr = parse_expr(&args[0])?;
+            Ok(Output::Expression(try_simplify(&try_differentiate(&expr, &args[1])?)?))
+        }
+        "integrate" => {
+            let args = expect_args(positional, &["expr", "var"])?;
+            let expr = parse_expr(&args[0])?;
+            Ok(Output::Expression(try_simplify(&try_integrate(&expr, &args[1])?)?))
+        }
+        "simplify" => {
+            let args = expect_args(positional, &["expr"])?;
+            Ok(Output::Expression(try_simplify(&parse_expr(&args[0])?)?))
+        }
+        "batch" => {
+            let args = expect_args(positional, &["expr", "file.csv"])?;
+            let expr = parse_expr(&args[0])?;
+            batch(&expr, &args[1], options)?;
+            Ok(Output::Written)
+        }
+        "help" => Err(CliError::Usage(USAGE.to_string())),
+        other => Err(CliError::Usage(format!("unknown command `{}`\n\n{}", other, USAGE))),
+    }
+}
+
+fn batch(expr: &Expr, path: &str, options: &Options) -> Result<(), CliError> {
+    let failed = |err: io::Error| CliError::Failed(format!("error: {}\n", err));
+
+    let input: Box<dyn BufRead> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path).map_err(failed)?))
+    };
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(File::create(path).map_err(failed)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut output = BufWriter::new(output);
+
+    let summary = evaluate_csv(expr, input, &mut output, &options.column)
+        .map_err(|err| CliError::Failed(format!("error: {}\n", err)))?;
+    output.flush().map_err(failed)?;
+
+    // Every row has been written by now, failing rows only change the exit code
+    if summary.errors.is_empty() {
+        return Ok(());
+    }
+    let mut message = String::new();
+    for error in &summary.errors {
+        message.push_str(&format!("line {}: {}\n", error.line, error.message));
+    }
+    message.push_str(&format!("{} of {} rows failed\n", summary.errors.len(), summary.rows));
+    Err(CliError::Failed(message))
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn render(output: &Output, format: &Format) -> Option<String> {
+    let text = match (output, format) {
+        (Output::Written, _) => return None,
+        (Output::Value(value), Format::Plain | Format::Latex) => value.to_string(),
+        // JSON has no infinity
+        (Output::Value(value), Format::Json) if !value.is_finite() => "{\"result\": null}".to_string(),
+        (Output::Value(value), Format::Json) => format!("{{\"result\": {}}}", value),
+        (Output::Expression(expr), Format::Plain) => to_string(expr),
+        (Output::Expression(expr), Format::Latex) => to_latex(expr),
+        (Output::Expression(expr), Format::Json) => format!(
+            "{{\"result\": {}, \"latex\": {}}}",
+            json_string(&to_string(expr)),
+            json_string(&to_latex(expr))
+        ),
+    };
+    Some(text)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.first().is_some_and(|arg| arg == "repl") {
+        repl();
+        return ExitCode::SUCCESS;
+    }
+
+    let result = parse_options(&args).and_then(|options| {
+        let output = run(&options)?;
+        Ok(render(&output, &options.format))
+    });
+
+    match result {
+        Ok(text) => {
+            if let Some(text) = text {
+                println!("{}", text);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(CliError::Failed(message)) => {
+            eprint!("{}", message);
+            ExitCode::from(1)
+        }
+        Err(CliError::Usage(message)) => {
+            eprintln!("{}", message);
+            ExitCode::from(2)
+        }
+    }
 }
@@ -0,0 +1,488 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::expr::{to_string, Expr};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Range<usize>, // byte range into the input, empty when pointing at the end
+    pub expected: Vec<String>, // tokens that would have been accepted here
+}
+
+impl ParseError {
+    fn new(message: String, span: Range<usize>, expected: &[&str]) -> Self {
+        ParseError {
+            message,
+            span,
+            expected: expected.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    // Render the error against the source it came from:
+    //
+    // error: unclosed `(`
+    //   |
+    // 1 | max(x, 2
+    //   |    ^~~~~
+    //   = expected `,` or `)`
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let end = self.span.end.clamp(start, source.len());
+
+        // Locate the line holding the start of the span
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let line = &source[line_start..line_end];
+
+        // Columns are counted in characters so non-ASCII names like `θ` line up
+        let column = source[line_start..start].chars().count();
+        let width = source[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        let mut out = format!("error: {}\n", self.message);
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line_number, line));
+        out.push_str(&format!(
+            "{} | {}^{}\n",
+            gutter,
+            " ".repeat(column),
+            "~".repeat(width - 1)
+        ));
+        if !self.expected.is_empty() {
+            out.push_str(&format!("{} = expected {}\n", gutter, self.expected_list()));
+        }
+        out
+    }
+
+    fn expected_list(&self) -> String {
+        match self.expected.as_slice() {
+            [] => String::new(),
+            [only] => only.clone(),
+            [init @ .., last] => format!("{} or {}", init.join(", "), last),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// What may start an operand, used for the expected set of most errors
+const OPERAND: &[&str] = &["number", "identifier", "`(`", "`-`"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LParen,
+    RParen,
+    Comma,
+    End,
+}
+
+impl Token {
+    fn is_binary_operator(&self) -> bool {
+        matches!(self, Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Caret)
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => format!("number `{}`", n),
+        Token::Ident(name) => format!("identifier `{}`", name),
+        Token::Plus => "`+`".to_string(),
+        Token::Minus => "`-`".to_string(),
+        Token::Star => "`*`".to_string(),
+        Token::Slash => "`/`".to_string(),
+        Token::Caret => "`^`".to_string(),
+        Token::LParen => "`(`".to_string(),
+        Token::RParen => "`)`".to_string(),
+        Token::Comma => "`,`".to_string(),
+        Token::End => "end of input".to_string(),
+    }
+}
+
+// Split the input into (token, byte span) pairs, always ending with `Token::End`
+fn tokenize(input: &str) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c.is_ascii_digit() || c == '.' {
+            let end = number_end(input, start);
+            let text = &input[start..end];
+            let value = text.parse::<f64>().map_err(|_| {
+                ParseError::new(format!("invalid number `{}`", text), start..end, &["number"])
+            })?;
+            tokens.push((Token::Number(value), start..end));
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    end = i + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let name = &input[start..end];
+            // `to_string` prints non-finite numbers as `inf` and `NaN`
+            let token = match name {
+                "inf" => Token::Number(f64::INFINITY),
+                "NaN" => Token::Number(f64::NAN),
+                _ => Token::Ident(name.to_string()),
+            };
+            tokens.push((token, start..end));
+            continue;
+        }
+
+        let token = match c {
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '^' => Token::Caret,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            _ => {
+                return Err(ParseError::new(
+                    format!("unknown character `{}`", c),
+                    start..start + c.len_utf8(),
+                    &[],
+                ));
+            }
+        };
+        tokens.push((token, start..start + 1));
+        chars.next();
+    }
+
+    tokens.push((Token::End, input.len()..input.len()));
+    Ok(tokens)
+}
+
+// Find where a numeric literal starting at `start` ends: digits, an optional
+// fraction and an optional exponent like `e-3`
+fn number_end(input: &str, start: usize) -> usize {
+    let bytes = input.as_bytes();
+    let mut i = start;
+
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    if i < bytes.len() && bytes[i] == b'.' {
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+            j += 1;
+        }
+        // Only treat it as an exponent if digits follow, otherwise `e` is left for the identifier
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            i = j;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+
+    i
+}
+
+struct Parser {
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.tokens[self.pos].1.clone()
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &[&str]) -> ParseError {
+        let message = match self.peek() {
+            Token::RParen => "unmatched `)`".to_string(),
+            token => format!("unexpected {}", describe(token)),
+        };
+        ParseError::new(message, self.span(), expected)
+    }
+
+    // An operand was required after the binary operator at `operator`
+    fn missing_operand(&self, operator: usize) -> ParseError {
+        let (token, span) = &self.tokens[operator];
+        ParseError::new(
+            format!("operator {} is missing its right operand", describe(token)),
+            span.clone(),
+            OPERAND,
+        )
+    }
+
+    // Parse the right operand of the binary operator that was just consumed
+    fn operand<F>(&mut self, parse: F) -> Result<Expr, ParseError>
+    where
+        F: FnOnce(&mut Self) -> Result<Expr, ParseError>,
+    {
+        let operator = self.pos - 1;
+        let next = self.peek();
+        if matches!(next, Token::End | Token::RParen | Token::Comma) || next.is_binary_operator() && *next != Token::Minus {
+            return Err(self.missing_operand(operator));
+        }
+        parse(self)
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.term()?;
+        loop {
+            match self.peek() {
+                Token::Plus => {
+                    self.advance();
+                    let right = self.operand(Self::term)?;
+                    left = Expr::Add(Box::new(left), Box::new(right));
+                }
+                Token::Minus => {
+                    self.advance();
+                    let right = self.operand(Self::term)?;
+                    left = Expr::Sub(Box::new(left), Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            match self.peek() {
+                Token::Star => {
+                    self.advance();
+                    let right = self.operand(Self::unary)?;
+                    left = Expr::Mul(Box::new(left), Box::new(right));
+                }
+                Token::Slash => {
+                    self.advance();
+                    let right = self.operand(Self::unary)?;
+                    left = Expr::Div(Box::new(left), Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    // unary := '-' unary | power
+    // Minus binds looser than ^ whatever its operand, so `-2 ^ 2` is -(2 ^ 2)
+    // like `-x ^ 2`; a plain `-2` is still the literal Number(-2)
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if *self.peek() == Token::Minus {
+            self.advance();
+            return Ok(match self.operand(Self::unary)? {
+                Expr::Number(n) => Expr::Number(-n),
+                operand => Expr::Mul(Box::new(Expr::Number(-1.0)), Box::new(operand)),
+            });
+        }
+        self.power()
+    }
+
+    // power := atom ('^' unary)?, right associative
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if *self.peek() == Token::Caret {
+            self.advance();
+            let exponent = self.operand(Self::unary)?;
+            return Ok(Expr::Pow(Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    // atom := number | identifier | identifier '(' arguments ')' | '(' expression ')'
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().clone() {
+            Token::Number(n) => {
+                self.advance();
+                Ok(Expr::Number(n))
+            }
+            Token::Ident(name) => {
+                let name_span = self.span();
+                self.advance();
+                if *self.peek() == Token::LParen {
+                    let open = self.span();
+                    self.advance();
+                    let args = self.arguments(&name, name_span.start, open)?;
+                    Ok(Expr::Func(name, args))
+                } else {
+                    Ok(Expr::Variable(name))
+                }
+            }
+            Token::LParen => {
+                let open = self.span();
+                self.advance();
+                if *self.peek() == Token::RParen {
+                    return Err(ParseError::new(
+                        "empty parentheses".to_string(),
+                        open.start..self.span().end,
+                        OPERAND,
+                    ));
+                }
+                let inner = self.expression()?;
+                self.close(open, &["`)`"])?;
+                Ok(inner)
+            }
+            _ => Err(self.unexpected(OPERAND)),
+        }
+    }
+
+    // Consume the `)` matching the `(` at `open`
+    fn close(&mut self, open: Range<usize>, expected: &[&str]) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::RParen => {
+                self.advance();
+                Ok(())
+            }
+            // Running out of input means the `(` was never closed, so point at it
+            Token::End => Err(ParseError::new(
+                "unclosed `(`".to_string(),
+                open.start..self.span().end,
+                expected,
+            )),
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    // arguments := expression (',' expression)* ')'
+    fn arguments(&mut self, name: &str, start: usize, open: Range<usize>) -> Result<Vec<Expr>, ParseError> {
+        if *self.peek() == Token::RParen {
+            return Err(ParseError::new(
+                format!("function `{}` called with an empty argument list", name),
+                start..self.span().end,
+                OPERAND,
+            ));
+        }
+
+        let mut args = Vec::new();
+        loop {
+            // Catch `f(x,)` and `f(, x)` before they surface as a generic error
+            if matches!(self.peek(), Token::Comma | Token::RParen) {
+                return Err(ParseError::new(
+                    format!("empty argument in call to `{}`", name),
+                    self.span(),
+                    OPERAND,
+                ));
+            }
+            args.push(self.expression()?);
+            if *self.peek() == Token::Comma {
+                self.advance();
+                continue;
+            }
+            self.close(open.clone(), &["`,`", "`)`"])?;
+            return Ok(args);
+        }
+    }
+}
+
+// A user-defined function, `name(params) = body`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+// Parse a definition like `f(x, y) = x ^ 2 + y`. Only the shape is checked
+// here, `FunctionRegistry::define` checks the body against the parameters.
+pub fn parse_definition(input: &str) -> Result<Definition, ParseError> {
+    let equals = input
+        .find('=')
+        .ok_or_else(|| ParseError::new("expected `=` in definition".to_string(), input.len()..input.len(), &["`=`"]))?;
+
+    let head = input[..equals].trim_end();
+    let (name, args) = match parse(head)? {
+        Expr::Func(name, args) => (name, args),
+        _ => {
+            let start = input.len() - input.trim_start().len();
+            return Err(ParseError::new(
+                "expected a function like `f(x, y)` before `=`".to_string(),
+                start..head.len().max(start + 1),
+                &["identifier"],
+            ));
+        }
+    };
+    let mut params = Vec::new();
+    for arg in args {
+        match arg {
+            Expr::Variable(param) => params.push(param),
+            other => {
+                return Err(ParseError::new(
+                    format!("parameters of `{}` must be plain names, found `{}`", name, to_string(&other)),
+                    0..head.len(),
+                    &["identifier"],
+                ));
+            }
+        }
+    }
+
+    // Spans in the body are relative to the text after `=`
+    let offset = equals + 1;
+    let body = parse(&input[offset..]).map_err(|mut err| {
+        err.span = err.span.start + offset..err.span.end + offset;
+        err
+    })?;
+
+    Ok(Definition { name, params, body })
+}
+
+// Parse an infix expression like `sin(x) ^ 2 + max(x, 2.5e-3)` into an `Expr`.
+// `parse(&to_string(&expr))` gives back `expr` for every tree `to_string` can
+// print, except calls without arguments, which are rejected.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    if *parser.peek() == Token::End {
+        return Err(ParseError::new("empty expression".to_string(), parser.span(), OPERAND));
+    }
+    let expr = parser.expression()?;
+    if *parser.peek() != Token::End {
+        return Err(parser.unexpected(&["an operator", "end of input"]));
+    }
+    Ok(expr)
+}
@@ -0,0 +1,449 @@
+use crate::expr::Expr;
+
+// Relative size below which a computed coefficient counts as zero
+const TOLERANCE: f64 = 1e-9;
+
+// Coefficients from the constant term up, without trailing zeros
+#[derive(Debug, Clone, PartialEq)]
+struct Polynomial(Vec<f64>);
+
+impl Polynomial {
+    fn new(mut coefficients: Vec<f64>) -> Self {
+        while coefficients.last() == Some(&0.0) {
+            coefficients.pop();
+        }
+        Polynomial(coefficients)
+    }
+
+    fn constant(c: f64) -> Self {
+        Polynomial::new(vec![c])
+    }
+
+    fn x() -> Self {
+        Polynomial::new(vec![0.0, 1.0])
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The zero polynomial has degree 0 here, like a constant
+    fn degree(&self) -> usize {
+        self.0.len().saturating_sub(1)
+    }
+
+    fn lead(&self) -> f64 {
+        self.0.last().copied().unwrap_or(0.0)
+    }
+
+    fn scale(&self) -> f64 {
+        self.0.iter().fold(0.0, |max, c| max.max(c.abs()))
+    }
+
+    fn coefficient(&self, i: usize) -> f64 {
+        self.0.get(i).copied().unwrap_or(0.0)
+    }
+
+    fn add(&self, other: &Polynomial) -> Polynomial {
+        let n = self.0.len().max(other.0.len());
+        Polynomial::new((0..n).map(|i| self.coefficient(i) + other.coefficient(i)).collect())
+    }
+
+    fn sub(&self, other: &Polynomial) -> Polynomial {
+        self.add(&other.times(-1.0))
+    }
+
+    fn times(&self, c: f64) -> Polynomial {
+        Polynomial::new(self.0.iter().map(|a| a * c).collect())
+    }
+
+    fn mul(&self, other: &Polynomial) -> Polynomial {
+        if self.is_zero() || other.is_zero() {
+            return Polynomial(Vec::new());
+        }
+        let mut product = vec![0.0; self.0.len() + other.0.len() - 1];
+        for (i, a) in self.0.iter().enumerate() {
+            for (j, b) in other.0.iter().enumerate() {
+                product[i + j] += a * b;
+            }
+        }
+        Polynomial::new(product)
+    }
+
+    fn pow(&self, n: u32) -> Polynomial {
+        (0..n).fold(Polynomial::constant(1.0), |power, _| power.mul(self))
+    }
+
+    fn derivative(&self) -> Polynomial {
+        Polynomial::new(self.0.iter().enumerate().skip(1).map(|(i, c)| i as f64 * c).collect())
+    }
+
+    fn monic(&self) -> Polynomial {
+        self.times(1.0 / self.lead())
+    }
+
+    // Drop coefficients that are only rounding noise next to `scale`
+    fn cleaned(&self, scale: f64) -> Polynomial {
+        Polynomial::new(self.0.iter().map(|&c| if c.abs() <= TOLERANCE * scale { 0.0 } else { c }).collect())
+    }
+
+    // Long division: self = quotient * divisor + remainder
+    fn div_rem(&self, divisor: &Polynomial) -> (Polynomial, Polynomial) {
+        let scale = self.scale().max(divisor.scale());
+        if self.0.len() < divisor.0.len() {
+            return (Polynomial(Vec::new()), self.clone());
+        }
+        let mut remainder = self.0.clone();
+        let mut quotient = vec![0.0; self.0.len() - divisor.0.len() + 1];
+        for i in (0..quotient.len()).rev() {
+            let c = remainder[i + divisor.degree()] / divisor.lead();
+            quotient[i] = c;
+            for (j, d) in divisor.0.iter().enumerate() {
+                remainder[i + j] -= c * d;
+            }
+        }
+        remainder.truncate(divisor.degree());
+        (Polynomial::new(quotient), Polynomial::new(remainder).cleaned(scale))
+    }
+
+    // Monic greatest common divisor, by Euclid's algorithm
+    fn gcd(&self, other: &Polynomial) -> Polynomial {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let r = a.div_rem(&b).1;
+            a = b;
+            b = r;
+        }
+        a.monic()
+    }
+
+    fn to_expr(&self, var: &str) -> Expr {
+        let mut terms = self.0.iter().enumerate().rev().filter(|(_, c)| **c != 0.0);
+        let term = |i: usize, c: f64| {
+            let power = match i {
+                0 => return Expr::Number(c),
+                1 => Expr::Variable(var.to_string()),
+                _ => Expr::Pow(Box::new(Expr::Variable(var.to_string())), Box::new(Expr::Number(i as f64))),
+            };
+            if c == 1.0 { power } else { Expr::Mul(Box::new(Expr::Number(c)), Box::new(power)) }
+        };
+        let Some((i, c)) = terms.next() else {
+            return Expr::Number(0.0);
+        };
+        terms.fold(term(i, *c), |sum, (i, c)| {
+            if *c < 0.0 {
+                Expr::Sub(Box::new(sum), Box::new(term(i, -c)))
+            } else {
+                Expr::Add(Box::new(sum), Box::new(term(i, *c)))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn add(self, o: Complex) -> Complex {
+        Complex { re: self.re + o.re, im: self.im + o.im }
+    }
+
+    fn sub(self, o: Complex) -> Complex {
+        Complex { re: self.re - o.re, im: self.im - o.im }
+    }
+
+    fn mul(self, o: Complex) -> Complex {
+        Complex { re: self.re * o.re - self.im * o.im, im: self.re * o.im + self.im * o.re }
+    }
+
+    fn div(self, o: Complex) -> Complex {
+        let d = o.re * o.re + o.im * o.im;
+        Complex { re: (self.re * o.re + self.im * o.im) / d, im: (self.im * o.re - self.re * o.im) / d }
+    }
+
+    fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+// Roots of a monic polynomial without repeated roots, by Durand–Kerner
+fn roots(p: &Polynomial) -> Vec<Complex> {
+    let n = p.degree();
+    let seed = Complex { re: 0.4, im: 0.9 };
+    let mut zs: Vec<Complex> = (0..n).scan(Complex { re: 1.0, im: 0.0 }, |z, _| {
+        *z = z.mul(seed);
+        Some(*z)
+    }).collect();
+
+    let eval = |z: Complex| {
+        p.0.iter().rev().fold(Complex { re: 0.0, im: 0.0 }, |acc, &c| acc.mul(z).add(Complex { re: c, im: 0.0 }))
+    };
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..n {
+            let denominator = (0..n)
+                .filter(|&j| j != i)
+                .fold(Complex { re: 1.0, im: 0.0 }, |acc, j| acc.mul(zs[i].sub(zs[j])));
+            let step = eval(zs[i]).div(denominator);
+            zs[i] = zs[i].sub(step);
+            change = change.max(step.abs());
+        }
+        if change < 1e-15 {
+            break;
+        }
+    }
+    zs
+}
+
+// An irreducible real factor of the denominator
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Factor {
+    Linear(f64), // x - r
+    Quadratic(f64, f64), // x^2 + p x + s, with p^2 < 4s
+}
+
+impl Factor {
+    fn polynomial(&self) -> Polynomial {
+        match *self {
+            Factor::Linear(r) => Polynomial::new(vec![-r, 1.0]),
+            Factor::Quadratic(p, s) => Polynomial::new(vec![s, p, 1.0]),
+        }
+    }
+}
+
+// Round values that are integers up to rounding noise, so 1/(x^2 - 1) gives
+// ln(abs(x - 1)) rather than ln(abs(x - 0.9999999999999998))
+fn snap(v: f64) -> f64 {
+    let rounded = v.round();
+    if (v - rounded).abs() <= 1e-9 * v.abs().max(1.0) { rounded + 0.0 } else { v }
+}
+
+// Factor a monic polynomial into irreducible real factors with multiplicities,
+// splitting off repeated roots first (Yun's square-free factorization) so that
+// the root finder only ever sees simple roots
+fn factor(d: &Polynomial) -> Vec<(Factor, u32)> {
+    let mut factors = Vec::new();
+    let mut b = d.div_rem(&d.gcd(&d.derivative())).0;
+    let mut c = d.derivative().div_rem(&d.gcd(&d.derivative())).0;
+    let mut multiplicity = 1;
+    while b.degree() > 0 {
+        let dd = c.sub(&b.derivative()).cleaned(b.scale().max(c.scale()));
+        let a = if dd.is_zero() { b.monic() } else { b.gcd(&dd) };
+        let square_free = a.monic();
+        b = b.div_rem(&a).0;
+        c = dd.div_rem(&a).0;
+
+        let mut zs = roots(&square_free);
+        zs.sort_by(|a, b| a.re.total_cmp(&b.re));
+        for z in zs {
+            if z.im.abs() <= 1e-7 * (1.0 + z.abs()) {
+                factors.push((Factor::Linear(snap(z.re)), multiplicity));
+            } else if z.im > 0.0 {
+                // One factor for each conjugate pair
+                factors.push((Factor::Quadratic(snap(-2.0 * z.re), snap(z.re * z.re + z.im * z.im)), multiplicity));
+            }
+        }
+        multiplicity += 1;
+    }
+    factors
+}
+
+// Solve a square system by Gaussian elimination with partial pivoting
+fn solve(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| matrix[a][col].abs().total_cmp(&matrix[b][col].abs()))?;
+        if matrix[pivot][col].abs() < 1e-14 {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let (upper, lower) = matrix.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let f = row[col] / pivot_row[col];
+            for (a, b) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *a -= f * b;
+            }
+            rhs[col + 1 + offset] -= f * rhs[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| matrix[row][k] * x[k]).sum();
+        x[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(x)
+}
+
+// `expr` as numerator / denominator, both polynomials in `var` with numeric
+// coefficients
+fn rational(expr: &Expr, var: &str) -> Option<(Polynomial, Polynomial)> {
+    let one = Polynomial::constant(1.0);
+    match expr {
+        Expr::Number(c) => Some((Polynomial::constant(*c), one)),
+        Expr::Variable(name) if name == var => Some((Polynomial::x(), one)),
+        Expr::Add(left, right) | Expr::Sub(left, right) => {
+            let ((a, b), (c, d)) = (rational(left, var)?, rational(right, var)?);
+            let (ad, cb) = (a.mul(&d), c.mul(&b));
+            let numerator = if matches!(expr, Expr::Add(..)) { ad.add(&cb) } else { ad.sub(&cb) };
+            Some((numerator, b.mul(&d)))
+        }
+        Expr::Mul(left, right) => {
+            let ((a, b), (c, d)) = (rational(left, var)?, rational(right, var)?);
+            Some((a.mul(&c), b.mul(&d)))
+        }
+        Expr::Div(left, right) => {
+            let ((a, b), (c, d)) = (rational(left, var)?, rational(right, var)?);
+            Some((a.mul(&d), b.mul(&c)))
+        }
+        Expr::Pow(base, exponent) => match **exponent {
+            Expr::Number(n) if n.fract() == 0.0 && n.abs() <= 64.0 => {
+                let (a, b) = rational(base, var)?;
+                let k = n.abs() as u32;
+                if n >= 0.0 { Some((a.pow(k), b.pow(k))) } else { Some((b.pow(k), a.pow(k))) }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn num(c: f64) -> Expr {
+    Expr::Number(snap(c))
+}
+
+fn mul(left: Expr, right: Expr) -> Expr {
+    Expr::Mul(Box::new(left), Box::new(right))
+}
+
+fn div(left: Expr, right: Expr) -> Expr {
+    Expr::Div(Box::new(left), Box::new(right))
+}
+
+fn add(left: Expr, right: Expr) -> Expr {
+    Expr::Add(Box::new(left), Box::new(right))
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    Expr::Func(name.to_string(), vec![arg])
+}
+
+fn power(base: Expr, n: f64) -> Expr {
+    Expr::Pow(Box::new(base), Box::new(Expr::Number(n)))
+}
+
+// ∫dt / (t^2 + a2)^k, by the reduction formula
+// I_k = t / (2 a2 (k - 1) (t^2 + a2)^(k - 1)) + (2k - 3) / (2 a2 (k - 1)) I_(k - 1)
+fn reduction(t: &Expr, q: &Expr, a2: f64, k: u32) -> Expr {
+    if k == 1 {
+        let a = a2.sqrt();
+        return mul(num(1.0 / a), call("atan", div(t.clone(), num(a))));
+    }
+    let kf = k as f64;
+    let head = div(t.clone(), mul(num(2.0 * a2 * (kf - 1.0)), power(q.clone(), kf - 1.0)));
+    let tail = mul(num((2.0 * kf - 3.0) / (2.0 * a2 * (kf - 1.0))), reduction(t, q, a2, k - 1));
+    add(head, tail)
+}
+
+// The antiderivative of a rational function with numeric coefficients, by
+// polynomial division and partial fractions over the reals; `None` when
+// `expr` is not such a function
+pub(crate) fn integrate_rational(expr: &Expr, var: &str) -> Option<Expr> {
+    let (numerator, denominator) = rational(expr, var)?;
+    if denominator.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = numerator.div_rem(&denominator);
+
+    // ∫q(x) term by term
+    let mut integral = Polynomial::new(
+        std::iter::once(0.0)
+            .chain(quotient.0.iter().enumerate().map(|(i, c)| snap(c / (i as f64 + 1.0))))
+            .collect(),
+    )
+    .to_expr(var);
+    if remainder.is_zero() {
+        return Some(integral);
+    }
+
+    // r / d = (r / lead) / monic d
+    let lead = denominator.lead();
+    let monic = denominator.monic();
+    let remainder = remainder.times(1.0 / lead);
+    let factors = factor(&monic);
+
+    // The factorization can merge roots closer than TOLERANCE, as in
+    // (x - 1)(x - 1.000001), and then gives a wrong answer; only go on if the
+    // factors multiply back to the denominator up to rounding
+    let product = factors.iter().fold(Polynomial::constant(1.0), |p, (f, k)| p.mul(&f.polynomial().pow(*k)));
+    let rounding = 64.0 * f64::EPSILON * monic.scale();
+    if (0..=product.degree().max(monic.degree())).any(|i| (product.coefficient(i) - monic.coefficient(i)).abs() > rounding) {
+        return None;
+    }
+
+    // Unknown coefficients, one column each: A / f^k for linear factors and
+    // (B x + C) / f^k for quadratic ones, multiplied through by d
+    let mut columns = Vec::new();
+    for &(f, multiplicity) in &factors {
+        for k in 1..=multiplicity {
+            let cofactor = monic.div_rem(&f.polynomial().pow(k)).0;
+            if let Factor::Quadratic(..) = f {
+                columns.push((f, k, true, cofactor.mul(&Polynomial::x())));
+            }
+            columns.push((f, k, false, cofactor));
+        }
+    }
+    let n = monic.degree();
+    if columns.len() != n {
+        return None;
+    }
+    let matrix = (0..n).map(|i| columns.iter().map(|column| column.3.coefficient(i)).collect()).collect();
+    let rhs = (0..n).map(|i| remainder.coefficient(i)).collect();
+    let coefficients = solve(matrix, rhs)?;
+
+    for (i, &(f, k, is_x, _)) in columns.iter().enumerate() {
+        // The x coefficient of a quadratic term is picked up with its constant
+        if is_x {
+            continue;
+        }
+        let c = snap(coefficients[i]);
+        let term = match f {
+            Factor::Linear(_) if c == 0.0 => continue,
+            Factor::Linear(r) => {
+                let shifted = Polynomial::new(vec![-r, 1.0]).to_expr(var);
+                if k == 1 {
+                    // ∫A / (x - r) = A ln|x - r|
+                    mul(num(c), call("ln", call("abs", shifted)))
+                } else {
+                    // ∫A / (x - r)^k = A (x - r)^(1 - k) / (1 - k)
+                    mul(num(c / (1.0 - k as f64)), power(shifted, 1.0 - k as f64))
+                }
+            }
+            Factor::Quadratic(p, s) => {
+                // (B x + C) / q^k = B/2 q' / q^k + (C - B p / 2) / q^k, with q' = 2x + p
+                let b = snap(coefficients[i - 1]);
+                let q = f.polynomial().to_expr(var);
+                let log_part = match k {
+                    _ if b == 0.0 => None,
+                    1 => Some(mul(num(b / 2.0), call("ln", q.clone()))),
+                    _ => Some(mul(num(b / 2.0 / (1.0 - k as f64)), power(q.clone(), 1.0 - k as f64))),
+                };
+                // Completing the square, q = (x + p/2)^2 + (s - p^2/4)
+                let rest = snap(c - b * p / 2.0);
+                let t = Polynomial::new(vec![p / 2.0, 1.0]).to_expr(var);
+                let atan_part = (rest != 0.0).then(|| mul(num(rest), reduction(&t, &q, s - p * p / 4.0, k)));
+                match (log_part, atan_part) {
+                    (Some(l), Some(a)) => add(l, a),
+                    (Some(term), None) | (None, Some(term)) => term,
+                    (None, None) => continue,
+                }
+            }
+        };
+        integral = if integral == Expr::Number(0.0) { term } else { add(integral, term) };
+    }
+    Some(integral)
+}
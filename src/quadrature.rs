@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::f64::consts::FRAC_PI_2;
+
+use crate::error::ProtonError;
+use crate::eval::EvalContext;
+use crate::expr::Expr;
+use crate::finite_difference::Estimate;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    AdaptiveSimpson, // Simpson's rule, halving intervals until the halves agree
+    GaussKronrod15, // 7-point Gauss rule inside a 15-point Kronrod rule, bisecting the worst interval
+    GaussKronrod21, // the same with 10 and 21 points
+    TanhSinh, // double-exponential nodes crowding the endpoints, for singularities there
+}
+
+// Stop once the error estimate is below TOLERANCE relative to the value (or
+// absolutely, for values below 1), or once MAX_EVALUATIONS points are spent
+const TOLERANCE: f64 = 1e-10;
+const MAX_EVALUATIONS: usize = 100_000;
+const MAX_SIMPSON_DEPTH: usize = 50;
+
+// Gauss–Kronrod takes an interval that has been bisected DIVERGENCE_BISECTIONS
+// times, and whose half still holds DIVERGENCE_RATIO of it, for a
+// non-integrable singularity
+const DIVERGENCE_BISECTIONS: usize = 30;
+const DIVERGENCE_RATIO: f64 = 0.99;
+
+// Tanh–sinh sums nodes up to |t| = TANH_SINH_RANGE, past which the weights
+// vanish in double precision, halving the step up to MAX_TANH_SINH_LEVEL times
+const TANH_SINH_RANGE: f64 = 3.5;
+const MAX_TANH_SINH_LEVEL: usize = 12;
+
+// Nodes on [0, 1) of the Kronrod rules, from QUADPACK; every second one,
+// starting from the second, is also a node of the embedded Gauss rule
+const KRONROD_15_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.0,
+];
+const KRONROD_15_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_2,
+    0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_8,
+];
+const GAUSS_7_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+const KRONROD_21_NODES: [f64; 11] = [
+    0.995_657_163_025_808_1,
+    0.973_906_528_517_171_7,
+    0.930_157_491_355_708_2,
+    0.865_063_366_688_984_5,
+    0.780_817_726_586_416_9,
+    0.679_409_568_299_024_4,
+    0.562_757_134_668_604_7,
+    0.433_395_394_129_247_2,
+    0.294_392_862_701_460_2,
+    0.148_874_338_981_631_2,
+    0.0,
+];
+const KRONROD_21_WEIGHTS: [f64; 11] = [
+    0.011_694_638_867_371_87,
+    0.032_558_162_307_964_73,
+    0.054_755_896_574_352,
+    0.075_039_674_810_919_95,
+    0.093_125_454_583_697_61,
+    0.109_387_158_802_297_6,
+    0.123_491_976_262_065_9,
+    0.134_709_217_311_473_3,
+    0.142_775_938_577_060_1,
+    0.147_739_104_901_338_5,
+    0.149_445_554_002_916_9,
+];
+const GAUSS_10_WEIGHTS: [f64; 5] = [
+    0.066_671_344_308_688_14,
+    0.149_451_349_150_580_6,
+    0.219_086_362_515_982,
+    0.269_266_719_309_996_4,
+    0.295_524_224_714_752_9,
+];
+
+// Infinite intervals are mapped onto finite ones; each variant says how t
+// maps back to x
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Map {
+    Finite, // x = t
+    Above(f64), // [a, ∞): x = a + t / (1 - t), t in [0, 1)
+    Below(f64), // (-∞, b]: x = b - t / (1 - t), t in [0, 1), with the orientation flip in dx
+    Everywhere, // (-∞, ∞): x = t / (1 - t^2), t in (-1, 1)
+}
+
+// The integrand in t, counting evaluations. `gave_up` is set when a method
+// stops refining before its error estimate met the tolerance
+struct Integrand<'a, 'b> {
+    expr: &'b Expr,
+    var: &'b str,
+    context: &'b EvalContext<'a>,
+    vars: HashMap<String, f64>,
+    map: Map,
+    evaluations: usize,
+    gave_up: bool,
+}
+
+impl Integrand<'_, '_> {
+    // x and dx/dt at t
+    fn map(&self, t: f64) -> (f64, f64) {
+        match self.map {
+            Map::Finite => (t, 1.0),
+            Map::Above(a) => (a + t / (1.0 - t), 1.0 / ((1.0 - t) * (1.0 - t))),
+            Map::Below(b) => (b - t / (1.0 - t), 1.0 / ((1.0 - t) * (1.0 - t))),
+            Map::Everywhere => (t / (1.0 - t * t), (1.0 + t * t) / ((1.0 - t * t) * (1.0 - t * t))),
+        }
+    }
+
+    fn at(&mut self, t: f64) -> Result<f64, ProtonError> {
+        let (x, dx) = self.map(t);
+        // The ends of a mapped interval are at infinity, where the integrand
+        // has to vanish for the integral to exist
+        if !x.is_finite() {
+            return Ok(0.0);
+        }
+        self.vars.insert(self.var.to_string(), x);
+        self.evaluations += 1;
+        let value = EvalContext { vars: &self.vars, ..*self.context }.evaluate(self.expr)?;
+        if !value.is_finite() {
+            return Err(ProtonError::NonFiniteValue {
+                var: self.var.to_string(),
+                at: x,
+            });
+        }
+        Ok(value * dx)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.evaluations >= MAX_EVALUATIONS
+    }
+}
+
+fn converged(value: f64, error: f64) -> bool {
+    error <= TOLERANCE * value.abs().max(1.0)
+}
+
+// ∫ expr d(var) from `lower` to `upper`, either of which may be infinite,
+// with every other variable taken from `vars`. An integrand that is not
+// finite at an evaluated point is an error, and so is an integral that
+// diverges or an estimate that never met the tolerance, with that estimate
+// in `NotConverged`
+pub fn quadrature(
+    expr: &Expr,
+    var: &str,
+    lower: f64,
+    upper: f64,
+    vars: &HashMap<String, f64>,
+    method: Method,
+) -> Result<Estimate, ProtonError> {
+    quadrature_with(expr, var, lower, upper, &EvalContext::new(vars), method)
+}
+
+// Like `quadrature`, evaluating with the unbound policy and function table of `context`
+pub fn quadrature_with(
+    expr: &Expr,
+    var: &str,
+    lower: f64,
+    upper: f64,
+    context: &EvalContext,
+    method: Method,
+) -> Result<Estimate, ProtonError> {
+    // NaN compares false with everything and would pass for an infinite bound
+    if let Some(bound) = [lower, upper].into_iter().find(|bound| bound.is_nan()) {
+        return Err(ProtonError::InvalidBound(bound));
+    }
+    if lower > upper {
+        let estimate = quadrature_with(expr, var, upper, lower, context, method)?;
+        return Ok(Estimate {
+            value: -estimate.value,
+            ..estimate
+        });
+    }
+    if lower == upper {
+        return Ok(Estimate {
+            value: 0.0,
+            error: 0.0,
+            evaluations: 0,
+        });
+    }
+
+    let (map, a, b) = match (lower.is_finite(), upper.is_finite()) {
+        (true, true) => (Map::Finite, lower, upper),
+        (true, false) => (Map::Above(lower), 0.0, 1.0),
+        (false, true) => (Map::Below(upper), 0.0, 1.0),
+        (false, false) => (Map::Everywhere, -1.0, 1.0),
+    };
+    let mut integrand = Integrand {
+        expr,
+        var,
+        context,
+        vars: context.vars.clone(),
+        map,
+        evaluations: 0,
+        gave_up: false,
+    };
+
+    let (value, error) = match method {
+        Method::AdaptiveSimpson => adaptive_simpson(&mut integrand, a, b)?,
+        Method::GaussKronrod15 => gauss_kronrod(&mut integrand, a, b, &KRONROD_15_NODES, &KRONROD_15_WEIGHTS, &GAUSS_7_WEIGHTS)?,
+        Method::GaussKronrod21 => gauss_kronrod(&mut integrand, a, b, &KRONROD_21_NODES, &KRONROD_21_WEIGHTS, &GAUSS_10_WEIGHTS)?,
+        Method::TanhSinh => tanh_sinh(&mut integrand, a, b)?,
+    };
+    // A rough value is worse than none when it looks like a real one
+    if integrand.gave_up {
+        return Err(ProtonError::NotConverged { value, error });
+    }
+
+    Ok(Estimate {
+        value,
+        error,
+        evaluations: integrand.evaluations,
+    })
+}
+
+fn adaptive_simpson(f: &mut Integrand, a: f64, b: f64) -> Result<(f64, f64), ProtonError> {
+    let (fa, fm, fb) = (f.at(a)?, f.at((a + b) / 2.0)?, f.at(b)?);
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+    simpson(f, (a, fa), (b, fb), fm, whole, TOLERANCE * whole.abs().max(1.0), MAX_SIMPSON_DEPTH)
+}
+
+// Splits [a, b] in half and compares the two halves with the whole; the
+// difference is 15 times the error of the halves
+fn simpson(
+    f: &mut Integrand,
+    (a, fa): (f64, f64),
+    (b, fb): (f64, f64),
+    fm: f64,
+    whole: f64,
+    tolerance: f64,
+    depth: usize,
+) -> Result<(f64, f64), ProtonError> {
+    let m = (a + b) / 2.0;
+    let (fl, fr) = (f.at((a + m) / 2.0)?, f.at((m + b) / 2.0)?);
+    let left = (m - a) / 6.0 * (fa + 4.0 * fl + fm);
+    let right = (b - m) / 6.0 * (fm + 4.0 * fr + fb);
+    let difference = left + right - whole;
+    if difference.abs() > 15.0 * tolerance && (depth == 0 || f.exhausted()) {
+        f.gave_up = true;
+    }
+    if depth == 0 || f.exhausted() || difference.abs() <= 15.0 * tolerance {
+        return Ok((left + right + difference / 15.0, difference.abs() / 15.0));
+    }
+    let (l, le) = simpson(f, (a, fa), (m, fm), fl, left, tolerance / 2.0, depth - 1)?;
+    let (r, re) = simpson(f, (m, fm), (b, fb), fr, right, tolerance / 2.0, depth - 1)?;
+    Ok((l + r, le + re))
+}
+
+// One Kronrod rule on [a, b], with the difference from its embedded Gauss
+// rule as the error
+fn kronrod(f: &mut Integrand, a: f64, b: f64, nodes: &[f64], weights: &[f64], gauss: &[f64]) -> Result<(f64, f64), ProtonError> {
+    let (center, half) = ((a + b) / 2.0, (b - a) / 2.0);
+    let (mut kronrod, mut gauss_sum) = (0.0, 0.0);
+    for (i, (&node, &weight)) in nodes.iter().zip(weights).enumerate() {
+        let value = if node == 0.0 {
+            f.at(center)?
+        } else {
+            f.at(center - half * node)? + f.at(center + half * node)?
+        };
+        kronrod += weight * value;
+        if i % 2 == 1 {
+            gauss_sum += gauss[i / 2] * value;
+        }
+    }
+    Ok((kronrod * half, ((kronrod - gauss_sum) * half).abs()))
+}
+
+// Global adaptive Gauss–Kronrod: keep bisecting whichever interval has the
+// largest error until the total is small enough
+fn gauss_kronrod(f: &mut Integrand, a: f64, b: f64, nodes: &[f64], weights: &[f64], gauss: &[f64]) -> Result<(f64, f64), ProtonError> {
+    let (value, error) = kronrod(f, a, b, nodes, weights, gauss)?;
+    // (a, b, value, error, bisections)
+    let mut intervals = vec![(a, b, value, error, 0)];
+    loop {
+        let value: f64 = intervals.iter().map(|interval| interval.2).sum();
+        let error: f64 = intervals.iter().map(|interval| interval.3).sum();
+        if converged(value, error) {
+            return Ok((value, error));
+        }
+        if f.exhausted() {
+            f.gave_up = true;
+            return Ok((value, error));
+        }
+        let worst = (0..intervals.len()).max_by(|&i, &j| intervals[i].3.total_cmp(&intervals[j].3)).unwrap();
+        let (a, b, whole, _, bisections) = intervals.swap_remove(worst);
+        let m = (a + b) / 2.0;
+        // Nothing left to split
+        if m <= a || m >= b {
+            f.gave_up = true;
+            return Ok((value, error));
+        }
+        let (left, left_error) = kronrod(f, a, m, nodes, weights, gauss)?;
+        let (right, right_error) = kronrod(f, m, b, nodes, weights, gauss)?;
+        // Near an integrable singularity like x^-α, α < 1, the half next to
+        // it holds 2^(α - 1) of the whole; a half that holds as much or more
+        // means the integral diverges, however small its relative error looks
+        if bisections >= DIVERGENCE_BISECTIONS && left.abs().max(right.abs()) >= DIVERGENCE_RATIO * whole.abs() {
+            return Err(ProtonError::Diverged {
+                var: f.var.to_string(),
+                near: f.map(m).0,
+            });
+        }
+        intervals.push((a, m, left, left_error, bisections + 1));
+        intervals.push((m, b, right, right_error, bisections + 1));
+    }
+}
+
+// Tanh–sinh: x = tanh(π/2 sinh t) sends the endpoints to t = ±∞ and the
+// weights decay double exponentially there, so integrable singularities at
+// the endpoints are never evaluated. Each level halves the step, reusing the
+// previous nodes; the change between levels is the error
+fn tanh_sinh(f: &mut Integrand, a: f64, b: f64) -> Result<(f64, f64), ProtonError> {
+    let (center, half) = ((a + b) / 2.0, (b - a) / 2.0);
+    // Weight at t, and the distance of the node from either endpoint
+    // relative to `half`, computed directly so it doesn't round to 0
+    let node = |t: f64| {
+        let u = FRAC_PI_2 * t.sinh();
+        let weight = FRAC_PI_2 * t.cosh() / (u.cosh() * u.cosh());
+        let distance = (-u).exp() / u.cosh();
+        (weight, distance)
+    };
+    let center_value = f.at(center)?;
+    // Both nodes at ±t; nodes that round onto an endpoint are dropped
+    let mut pair = |t: f64| -> Result<f64, ProtonError> {
+        let (weight, distance) = node(t);
+        let (left, right) = (a + half * distance, b - half * distance);
+        let mut sum = 0.0;
+        if left > a {
+            sum += f.at(left)?;
+        }
+        if right < b {
+            sum += f.at(right)?;
+        }
+        Ok(weight * sum)
+    };
+
+    let mut h = 1.0;
+    let mut sum = FRAC_PI_2 * center_value;
+    let steps = (TANH_SINH_RANGE / h) as usize;
+    for j in 1..=steps {
+        sum += pair(j as f64 * h)?;
+    }
+    let mut value = sum * h * half;
+    let mut error = f64::INFINITY;
+
+    for _ in 0..MAX_TANH_SINH_LEVEL {
+        h /= 2.0;
+        let steps = (TANH_SINH_RANGE / h) as usize;
+        for j in (1..=steps).step_by(2) {
+            sum += pair(j as f64 * h)?;
+        }
+        let refined = sum * h * half;
+        error = (refined - value).abs();
+        value = refined;
+        if converged(value, error) {
+            return Ok((value, error));
+        }
+    }
+    f.gave_up = true;
+    Ok((value, error))
+}
@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use crate::error::ProtonError;
+use crate::eval::EvalContext;
+use crate::expr::{substitute, variables, Expr};
+
+// Numeric implementation, called with the already evaluated arguments
+pub type NumericFn = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+// Derivative rule, called with the arguments and their derivatives with
+// respect to the variable being differentiated, e.g. for sin: (u, u') => cos(u) * u'
+pub type DerivativeFn = Arc<dyn Fn(&[Expr], &[Expr]) -> Expr + Send + Sync>;
+
+// Antiderivative of a single-argument function, called with its argument
+pub type AntiderivativeFn = Arc<dyn Fn(&Expr) -> Expr + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == *n,
+            Arity::AtLeast(n) => count >= *n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum FunctionBody {
+    Native(NumericFn),
+    // Defined in terms of other functions, like `f(x, y) = x ^ 2 + y`
+    Defined { params: Vec<String>, body: Expr },
+}
+
+#[derive(Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: Arity,
+    pub body: FunctionBody,
+    pub derivative: Option<DerivativeFn>,
+    pub antiderivative: Option<AntiderivativeFn>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Function");
+        debug.field("name", &self.name).field("arity", &self.arity);
+        if let FunctionBody::Defined { params, body } = &self.body {
+            debug.field("params", params).field("body", body);
+        }
+        debug
+            .field("derivative", &self.derivative.is_some())
+            .field("antiderivative", &self.antiderivative.is_some())
+            .finish()
+    }
+}
+
+impl Function {
+    pub fn new<F>(name: &str, arity: Arity, eval: F) -> Self
+    where
+        F: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    {
+        Function {
+            name: name.to_string(),
+            arity,
+            body: FunctionBody::Native(Arc::new(eval)),
+            derivative: None,
+            antiderivative: None,
+        }
+    }
+
+    // Shorthand for a single-argument function like `sigmoid`
+    pub fn unary<F>(name: &str, eval: F) -> Self
+    where
+        F: Fn(f64) -> f64 + Send + Sync + 'static,
+    {
+        Function::new(name, Arity::Exact(1), move |args| eval(args[0]))
+    }
+
+    pub fn with_derivative<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&[Expr], &[Expr]) -> Expr + Send + Sync + 'static,
+    {
+        self.derivative = Some(Arc::new(rule));
+        self
+    }
+
+    // Give the derivative as one partial derivative per argument, e.g. for
+    // hypot: (x, y) => [x / hypot(x, y), y / hypot(x, y)]. The full derivative
+    // then follows from the chain rule, f' = sum of ∂f/∂u_i * u_i'.
+    pub fn with_partials<F>(self, partials: F) -> Self
+    where
+        F: Fn(&[Expr]) -> Vec<Expr> + Send + Sync + 'static,
+    {
+        self.with_derivative(move |args, d_args| chain_rule(partials(args), d_args))
+    }
+
+    pub fn with_antiderivative<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&Expr) -> Expr + Send + Sync + 'static,
+    {
+        self.antiderivative = Some(Arc::new(rule));
+        self
+    }
+
+    // ∂f/∂u_i at `args`, from the derivative rule with u_i' = 1 and every other u_j' = 0
+    pub fn partial(&self, args: &[Expr], index: usize) -> Option<Expr> {
+        let rule = self.derivative.as_ref()?;
+        let unit: Vec<Expr> = (0..args.len())
+            .map(|i| Expr::Number(if i == index { 1.0 } else { 0.0 }))
+            .collect();
+        Some(rule(args, &unit))
+    }
+
+    pub fn check_arity(&self, found: usize) -> Result<(), ProtonError> {
+        if self.arity.accepts(found) {
+            Ok(())
+        } else {
+            Err(ProtonError::ArityMismatch {
+                name: self.name.clone(),
+                expected: self.arity,
+                found,
+            })
+        }
+    }
+}
+
+// The functions known to evaluation, differentiation, integration and
+// simplification. `FunctionRegistry::default()` holds the built-ins, more
+// can be added with `register`.
+#[derive(Debug, Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    // A registry without any functions, not even the built-ins
+    pub fn empty() -> Self {
+        FunctionRegistry { functions: HashMap::new() }
+    }
+
+    // Add a function, replacing any function with the same name
+    pub fn register(&mut self, function: Function) -> &mut Self {
+        self.functions.insert(function.name.clone(), function);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    // Look up `name`, failing with `UnknownFunction`
+    pub fn lookup(&self, name: &str) -> Result<&Function, ProtonError> {
+        self.get(name).ok_or_else(|| ProtonError::UnknownFunction(name.to_string()))
+    }
+
+    // Define `name(params) = body` in terms of the registered functions. The
+    // body may only use the parameters as variables, and definitions that
+    // would call themselves, directly or through other definitions, are rejected.
+    pub fn define(&mut self, name: &str, params: &[&str], body: Expr) -> Result<&mut Self, ProtonError> {
+        if let Some(Function { body: FunctionBody::Native(_), .. }) = self.get(name) {
+            return Err(ProtonError::InvalidDefinition(format!("`{}` is a built-in function", name)));
+        }
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                return Err(ProtonError::InvalidDefinition(format!("parameter `{}` appears twice", param)));
+            }
+        }
+        let free: Vec<String> = variables(&body)
+            .into_iter()
+            .filter(|var| !params.contains(&var.as_str()))
+            .collect();
+        if !free.is_empty() {
+            return Err(ProtonError::InvalidDefinition(format!(
+                "`{}` uses {} which {} not a parameter",
+                name,
+                free.join(", "),
+                if free.len() == 1 { "is" } else { "are" }
+            )));
+        }
+        self.check_calls(name, &body, &mut Vec::new())?;
+
+        self.register(Function {
+            name: name.to_string(),
+            arity: Arity::Exact(params.len()),
+            body: FunctionBody::Defined {
+                params: params.iter().map(|param| param.to_string()).collect(),
+                body,
+            },
+            derivative: None,
+            antiderivative: None,
+        });
+        Ok(self)
+    }
+
+    // Walk the calls made by `expr`, following definitions, to make sure they
+    // exist, get the right number of arguments and never lead back to `name`
+    fn check_calls<'a>(&'a self, name: &str, expr: &'a Expr, visited: &mut Vec<&'a str>) -> Result<(), ProtonError> {
+        match expr {
+            Expr::Number(_) | Expr::Variable(_) => Ok(()),
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Pow(left, right) => {
+                self.check_calls(name, left, visited)?;
+                self.check_calls(name, right, visited)
+            }
+            Expr::Func(callee, args) => {
+                if callee == name {
+                    return Err(ProtonError::RecursiveDefinition(name.to_string()));
+                }
+                let function = self.lookup(callee)?;
+                function.check_arity(args.len())?;
+                for arg in args {
+                    self.check_calls(name, arg, visited)?;
+                }
+                if let FunctionBody::Defined { body, .. } = &function.body
+                    && !visited.contains(&callee.as_str())
+                {
+                    visited.push(callee);
+                    self.check_calls(name, body, visited)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Call `name` with evaluated arguments, evaluating definitions through this registry
+    pub fn call(&self, name: &str, args: &[f64]) -> Result<f64, ProtonError> {
+        let function = self.lookup(name)?;
+        function.check_arity(args.len())?;
+        match &function.body {
+            FunctionBody::Native(eval) => Ok(eval(args)),
+            FunctionBody::Defined { params, body } => {
+                let bound: HashMap<String, f64> = params.iter().cloned().zip(args.iter().cloned()).collect();
+                EvalContext::new(&bound).with_functions(self).evaluate(body)
+            }
+        }
+    }
+
+    // The body of a defined function with `args` put in for its parameters,
+    // or `None` for native functions
+    pub fn instantiate(&self, name: &str, args: &[Expr]) -> Option<Expr> {
+        match &self.get(name)?.body {
+            FunctionBody::Defined { params, body } if params.len() == args.len() => {
+                let replacements: HashMap<String, Expr> = params.iter().cloned().zip(args.iter().cloned()).collect();
+                Some(substitute(body, &replacements))
+            }
+            _ => None,
+        }
+    }
+
+    // Replace every call to a defined function by its definition, so
+    // `to_string(&registry.expand(&expr))` prints the expansion rather than the call
+    pub fn expand(&self, expr: &Expr) -> Expr {
+        let expand = |e: &Expr| Box::new(self.expand(e));
+        match expr {
+            Expr::Number(_) | Expr::Variable(_) => expr.clone(),
+            Expr::Add(left, right) => Expr::Add(expand(left), expand(right)),
+            Expr::Sub(left, right) => Expr::Sub(expand(left), expand(right)),
+            Expr::Mul(left, right) => Expr::Mul(expand(left), expand(right)),
+            Expr::Div(left, right) => Expr::Div(expand(left), expand(right)),
+            Expr::Pow(left, right) => Expr::Pow(expand(left), expand(right)),
+            Expr::Func(name, args) => {
+                let args: Vec<Expr> = args.iter().map(|arg| self.expand(arg)).collect();
+                match self.instantiate(name, &args) {
+                    // Definitions can call other definitions
+                    Some(body) => self.expand(&body),
+                    None => Expr::Func(name.clone(), args),
+                }
+            }
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        let mut registry = FunctionRegistry::empty();
+        for function in builtin_functions() {
+            registry.register(function);
+        }
+        registry
+    }
+}
+
+// Shared registry of the built-ins, used by the entry points that don't take one
+pub fn builtins() -> &'static FunctionRegistry {
+    static BUILTINS: OnceLock<FunctionRegistry> = OnceLock::new();
+    BUILTINS.get_or_init(FunctionRegistry::default)
+}
+
+fn call(name: &str, arg: &Expr) -> Expr {
+    Expr::Func(name.to_string(), vec![arg.clone()])
+}
+
+// Sum of partial * derivative over the arguments, leaving out arguments
+// that do not depend on the variable
+fn chain_rule(partials: Vec<Expr>, d_args: &[Expr]) -> Expr {
+    partials
+        .into_iter()
+        .zip(d_args)
+        .filter(|(_, d_arg)| **d_arg != Expr::Number(0.0))
+        .map(|(partial, d_arg)| match d_arg {
+            Expr::Number(n) if *n == 1.0 => partial,
+            _ => mul(partial, d_arg.clone()),
+        })
+        .reduce(|sum, term| Expr::Add(Box::new(sum), Box::new(term)))
+        .unwrap_or(Expr::Number(0.0))
+}
+
+fn mul(left: Expr, right: Expr) -> Expr {
+    Expr::Mul(Box::new(left), Box::new(right))
+}
+
+fn div(left: Expr, right: Expr) -> Expr {
+    Expr::Div(Box::new(left), Box::new(right))
+}
+
+fn square(expr: Expr) -> Expr {
+    Expr::Pow(Box::new(expr), Box::new(Expr::Number(2.0)))
+}
+
+// sign(x) is -1, 0 or 1, unlike `f64::signum`, which is never 0
+fn sign(x: f64) -> f64 {
+    if x == 0.0 || x.is_nan() { x } else { x.signum() }
+}
+
+// (max(a, b))' = (a' + b' + sign(a - b) * (a' - b')) / 2, following
+// max(a, b) = (a + b + |a - b|) / 2, with `flip` = -1 giving min instead.
+// More arguments fold from the left: max(a, b, c) = max(max(a, b), c).
+fn extremum_derivative(name: &str, flip: f64, args: &[Expr], d_args: &[Expr]) -> Expr {
+    let (last, rest) = (args.len() - 1, &args[..args.len() - 1]);
+    if rest.is_empty() {
+        return d_args[0].clone();
+    }
+    let a = if rest.len() == 1 { rest[0].clone() } else { Expr::Func(name.to_string(), rest.to_vec()) };
+    let da = extremum_derivative(name, flip, rest, &d_args[..last]);
+    let (b, db) = (&args[last], &d_args[last]);
+
+    let difference = Expr::Sub(Box::new(a), Box::new(b.clone()));
+    let d_difference = Expr::Sub(Box::new(da.clone()), Box::new(db.clone()));
+    let jump = mul(mul(Expr::Number(flip), call("sign", &difference)), d_difference);
+    div(
+        Expr::Add(Box::new(Expr::Add(Box::new(da), Box::new(db.clone()))), Box::new(jump)),
+        Expr::Number(2.0),
+    )
+}
+
+fn builtin_functions() -> Vec<Function> {
+    vec![
+        Function::unary("sin", f64::sin)
+            // (sin(u))' = cos(u) * u'
+            .with_derivative(|u, du| Expr::Mul(Box::new(call("cos", &u[0])), Box::new(du[0].clone())))
+            // ∫sin(u) = -cos(u)
+            .with_antiderivative(|u| Expr::Mul(Box::new(Expr::Number(-1.0)), Box::new(call("cos", u)))),
+
+        Function::unary("cos", f64::cos)
+            // (cos(u))' = -sin(u) * u'
+            .with_derivative(|u, du| Expr::Mul(
+                Box::new(Expr::Mul(Box::new(Expr::Number(-1.0)), Box::new(call("sin", &u[0])))),
+                Box::new(du[0].clone()),
+            ))
+            // ∫cos(u) = sin(u)
+            .with_antiderivative(|u| call("sin", u)),
+
+        Function::unary("tan", f64::tan)
+            // (tan(u))' = sec^2(u) * u' = u' / cos^2(u)
+            .with_derivative(|u, du| Expr::Mul(
+                Box::new(Expr::Div(
+                    Box::new(Expr::Number(1.0)),
+                    Box::new(Expr::Pow(Box::new(call("cos", &u[0])), Box::new(Expr::Number(2.0)))),
+                )),
+                Box::new(du[0].clone()),
+            ))
+            // ∫tan(u) = -ln(cos(u)), where cos(u) > 0
+            .with_antiderivative(|u| Expr::Mul(Box::new(Expr::Number(-1.0)), Box::new(call("ln", &call("cos", u))))),
+
+        Function::unary("ln", f64::ln)
+            // (ln(u))' = u' / u
+            .with_derivative(|u, du| Expr::Div(Box::new(du[0].clone()), Box::new(u[0].clone())))
+            // ∫ln(u) = u * ln(u) - u
+            .with_antiderivative(|u| Expr::Sub(
+                Box::new(Expr::Mul(Box::new(u.clone()), Box::new(call("ln", u)))),
+                Box::new(u.clone()),
+            )),
+
+        Function::unary("log10", f64::log10)
+            // (log10(u))' = u' / (u * ln(10))
+            .with_derivative(|u, du| Expr::Div(
+                Box::new(du[0].clone()),
+                Box::new(Expr::Mul(Box::new(u[0].clone()), Box::new(call("ln", &Expr::Number(10.0))))),
+            ))
+            // ∫log10(u) = (u * ln(u) - u) / ln(10)
+            .with_antiderivative(|u| Expr::Div(
+                Box::new(Expr::Sub(
+                    Box::new(Expr::Mul(Box::new(u.clone()), Box::new(call("ln", u)))),
+                    Box::new(u.clone()),
+                )),
+                Box::new(call("ln", &Expr::Number(10.0))),
+            )),
+
+        Function::unary("sqrt", f64::sqrt)
+            // (sqrt(u))' = u' / (2 * sqrt(u))
+            .with_derivative(|u, du| Expr::Div(
+                Box::new(du[0].clone()),
+                Box::new(Expr::Mul(Box::new(Expr::Number(2.0)), Box::new(call("sqrt", &u[0])))),
+            ))
+            // ∫sqrt(u) = 2 * u^(3/2) / 3
+            .with_antiderivative(|u| Expr::Div(
+                Box::new(Expr::Mul(
+                    Box::new(Expr::Number(2.0)),
+                    Box::new(Expr::Pow(Box::new(u.clone()), Box::new(Expr::Number(1.5)))),
+                )),
+                Box::new(Expr::Number(3.0)),
+            )),
+
+        Function::new("log", Arity::Exact(2), |args| args[1].ln() / args[0].ln())
+            // log(b, u) = ln(u) / ln(b), so by the quotient rule
+            // (log(b, u))' = (u' / u * ln(b) - ln(u) * b' / b) / ln(b)^2
+            .with_derivative(|args, d_args| {
+                let (b, u) = (&args[0], &args[1]);
+                div(
+                    Expr::Sub(
+                        Box::new(mul(div(d_args[1].clone(), u.clone()), call("ln", b))),
+                        Box::new(mul(call("ln", u), div(d_args[0].clone(), b.clone()))),
+                    ),
+                    square(call("ln", b)),
+                )
+            }),
+
+        Function::new("pow", Arity::Exact(2), |args| args[0].powf(args[1]))
+            // ∂/∂a a^b = b * a^(b - 1), ∂/∂b a^b = a^b * ln(a)
+            .with_partials(|args| {
+                let (a, b) = (&args[0], &args[1]);
+                vec![
+                    mul(
+                        b.clone(),
+                        Expr::Pow(
+                            Box::new(a.clone()),
+                            Box::new(Expr::Sub(Box::new(b.clone()), Box::new(Expr::Number(1.0)))),
+                        ),
+                    ),
+                    mul(Expr::Func("pow".to_string(), args.to_vec()), call("ln", a)),
+                ]
+            }),
+
+        Function::new("atan2", Arity::Exact(2), |args| args[0].atan2(args[1]))
+            // atan2(y, x): ∂/∂y = x / (x^2 + y^2), ∂/∂x = -y / (x^2 + y^2)
+            .with_partials(|args| {
+                let (y, x) = (&args[0], &args[1]);
+                let r2 = Expr::Add(Box::new(square(x.clone())), Box::new(square(y.clone())));
+                vec![
+                    div(x.clone(), r2.clone()),
+                    mul(Expr::Number(-1.0), div(y.clone(), r2)),
+                ]
+            }),
+
+        Function::new("hypot", Arity::Exact(2), |args| args[0].hypot(args[1]))
+            // ∂/∂x hypot(x, y) = x / hypot(x, y), and the same for y
+            .with_partials(|args| {
+                let h = Expr::Func("hypot".to_string(), args.to_vec());
+                args.iter().map(|arg| div(arg.clone(), h.clone())).collect()
+            }),
+
+        Function::unary("exp", f64::exp)
+            // (exp(u))' = exp(u) * u'
+            .with_derivative(|u, du| mul(call("exp", &u[0]), du[0].clone()))
+            // ∫exp(u) = exp(u)
+            .with_antiderivative(|u| call("exp", u)),
+
+        Function::unary("sec", |x| 1.0 / x.cos())
+            // (sec(u))' = sec(u) * tan(u) * u'
+            .with_derivative(|u, du| mul(mul(call("sec", &u[0]), call("tan", &u[0])), du[0].clone())),
+
+        Function::unary("csc", |x| 1.0 / x.sin())
+            // (csc(u))' = -csc(u) * cot(u) * u'
+            .with_derivative(|u, du| mul(
+                mul(Expr::Number(-1.0), mul(call("csc", &u[0]), call("cot", &u[0]))),
+                du[0].clone(),
+            )),
+
+        Function::unary("cot", |x| 1.0 / x.tan())
+            // (cot(u))' = -u' / sin^2(u)
+            .with_derivative(|u, du| mul(Expr::Number(-1.0), div(du[0].clone(), square(call("sin", &u[0]))))),
+
+        Function::unary("asin", f64::asin)
+            // (asin(u))' = u' / sqrt(1 - u^2)
+            .with_derivative(|u, du| div(
+                du[0].clone(),
+                call("sqrt", &Expr::Sub(Box::new(Expr::Number(1.0)), Box::new(square(u[0].clone())))),
+            )),
+
+        Function::unary("acos", f64::acos)
+            // (acos(u))' = -u' / sqrt(1 - u^2)
+            .with_derivative(|u, du| mul(
+                Expr::Number(-1.0),
+                div(
+                    du[0].clone(),
+                    call("sqrt", &Expr::Sub(Box::new(Expr::Number(1.0)), Box::new(square(u[0].clone())))),
+                ),
+            )),
+
+        Function::unary("atan", f64::atan)
+            // (atan(u))' = u' / (1 + u^2)
+            .with_derivative(|u, du| div(
+                du[0].clone(),
+                Expr::Add(Box::new(Expr::Number(1.0)), Box::new(square(u[0].clone()))),
+            )),
+
+        Function::unary("sinh", f64::sinh)
+            // (sinh(u))' = cosh(u) * u'
+            .with_derivative(|u, du| mul(call("cosh", &u[0]), du[0].clone()))
+            // ∫sinh(u) = cosh(u)
+            .with_antiderivative(|u| call("cosh", u)),
+
+        Function::unary("cosh", f64::cosh)
+            // (cosh(u))' = sinh(u) * u'
+            .with_derivative(|u, du| mul(call("sinh", &u[0]), du[0].clone()))
+            // ∫cosh(u) = sinh(u)
+            .with_antiderivative(|u| call("sinh", u)),
+
+        Function::unary("tanh", f64::tanh)
+            // (tanh(u))' = u' / cosh^2(u)
+            .with_derivative(|u, du| div(du[0].clone(), square(call("cosh", &u[0])))),
+
+        Function::unary("abs", f64::abs)
+            // (|u|)' = sign(u) * u', undefined at u = 0 where sign gives 0
+            .with_derivative(|u, du| mul(call("sign", &u[0]), du[0].clone())),
+
+        Function::unary("sign", sign)
+            // Piecewise constant, so 0 away from the jump at u = 0
+            .with_derivative(|_, _| Expr::Number(0.0)),
+
+        Function::new("max", Arity::AtLeast(1), |args| {
+            args.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+        })
+        .with_derivative(|args, d_args| extremum_derivative("max", 1.0, args, d_args)),
+
+        Function::new("min", Arity::AtLeast(1), |args| {
+            args.iter().cloned().fold(f64::INFINITY, f64::min)
+        })
+        .with_derivative(|args, d_args| extremum_derivative("min", -1.0, args, d_args)),
+    ]
+}
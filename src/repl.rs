@@ -1,18 +1,378 @@
-use std::io::{self, Write};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::differentiate::try_differentiate_with;
+use crate::error::ProtonError;
+use crate::eval::EvalContext;
+use crate::expr::{to_string, Expr};
+use crate::integrate::try_integrate_with;
+use crate::parse::{parse, parse_definition};
+use crate::registry::FunctionRegistry;
+use crate::simplify::try_simplify_with;
+
+const HELP: &str = "\
+expr                 evaluate an expression, e.g. sin(x) ^ 2 + 1
+let x = expr         bind a variable for later expressions
+f(x, y) = expr       define a function, e.g. f(x, y) = x ^ 2 + y
+:diff expr, x        differentiate expr with respect to x
+:int expr, x         integrate expr with respect to x
+:simplify expr       simplify expr
+:expand expr         replace calls to defined functions by their definitions
+:vars                list bound variables
+:help                show this message
+exit                 leave the repl
+
+Lines with unclosed parentheses continue on the next line.";
+
+const COMMANDS: &[&str] = &[":diff", ":int", ":simplify", ":expand", ":vars", ":help"];
+
+pub struct Repl {
+    pub vars: HashMap<String, f64>,
+    pub functions: FunctionRegistry,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            vars: HashMap::new(),
+            functions: FunctionRegistry::default(),
+        }
+    }
+
+    // Read lines from `input` until `exit` or end of input, writing prompts and results to `output`
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            write!(output, ">>> ")?;
+            output.flush()?;
+
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+
+            // Keep reading while parentheses are unbalanced
+            while is_incomplete(&line) {
+                write!(output, "... ")?;
+                output.flush()?;
+                if input.read_line(&mut line)? == 0 {
+                    break;
+                }
+            }
+
+            let line = line.trim();
+            if line == "exit" {
+                return Ok(());
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            match self.execute(line) {
+                Ok(result) => writeln!(output, "{}", result)?,
+                Err(message) => write!(output, "{}", message)?,
+            }
+        }
+    }
+
+    // Run a single line, returning the text to show or a rendered error
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        if let Some(binding) = line.strip_prefix("let ") {
+            let (name, source) = binding
+                .split_once('=')
+                .ok_or_else(|| "error: expected `let name = expr`\n".to_string())?;
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(format!("error: `{}` is not a valid variable name\n", name));
+            }
+            let value = self.evaluate(&parse_source(source.trim())?)?;
+            self.vars.insert(name.to_string(), value);
+            return Ok(format!("{} = {}", name, value));
+        }
+
+        // `=` cannot appear in an expression, so anything else with one is a definition
+        if !line.starts_with(':') && line.contains('=') {
+            let definition = parse_definition(line).map_err(|err| err.render(line))?;
+            let params: Vec<&str> = definition.params.iter().map(String::as_str).collect();
+            self.functions
+                .define(&definition.name, &params, definition.body.clone())
+                .map_err(report)?;
+            return Ok(format!(
+                "{}({}) = {}",
+                definition.name,
+                params.join(", "),
+                to_string(&definition.body)
+            ));
+        }
+
+        if let Some(command) = line.strip_prefix(':') {
+            let (command, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            let rest = rest.trim();
+            return match command {
+                "diff" => {
+                    let (expr, var) = expr_and_variable(rest)?;
+                    let derivative = try_differentiate_with(&expr, &var, &self.functions)
+                        .and_then(|d| try_simplify_with(&d, &self.functions));
+                    Ok(to_string(&derivative.map_err(report)?))
+                }
+                "int" => {
+                    let (expr, var) = expr_and_variable(rest)?;
+                    let integral = try_integrate_with(&expr, &var, &self.functions)
+                        .and_then(|i| try_simplify_with(&i, &self.functions));
+                    Ok(to_string(&integral.map_err(report)?))
+                }
+                "simplify" => {
+                    let simplified = try_simplify_with(&parse_source(rest)?, &self.functions);
+                    Ok(to_string(&simplified.map_err(report)?))
+                }
+                "expand" => Ok(to_string(&self.functions.expand(&parse_source(rest)?))),
+                "vars" => {
+                    let mut names: Vec<&String> = self.vars.keys().collect();
+                    names.sort();
+                    let lines: Vec<String> = names
+                        .iter()
+                        .map(|name| format!("{} = {}", name, self.vars[*name]))
+                        .collect();
+                    Ok(lines.join("\n"))
+                }
+                "help" => Ok(HELP.to_string()),
+                _ => Err(format!("error: unknown command `:{}`, try :help\n", command)),
+            };
+        }
+
+        Ok(self.evaluate(&parse_source(line)?)?.to_string())
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<f64, String> {
+        EvalContext::new(&self.vars)
+            .with_functions(&self.functions)
+            .evaluate(expr)
+            .map_err(report)
+    }
+
+    // Tab completion for the word ending at byte `pos` of `line`: returns where
+    // the word starts and the function, variable or command names extending it
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let names = self.names();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        completions(line, pos, &names)
+    }
+
+    // Function and variable names, for completion
+    fn names(&self) -> Vec<String> {
+        let functions = self.functions.names().into_iter().map(str::to_string);
+        functions.chain(self.vars.keys().cloned()).collect()
+    }
+}
+
+fn completions(line: &str, pos: usize, names: &[&str]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map_or(pos, |(i, _)| i);
+    let word = &line[start..pos];
+
+    // A word right after the leading `:` is a command name
+    if start == 1 && line.starts_with(':') {
+        let candidates = COMMANDS
+            .iter()
+            .filter(|command| command[1..].starts_with(word))
+            .map(|command| command.to_string())
+            .collect();
+        return (0, candidates);
+    }
+
+    if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+        return (pos, Vec::new());
+    }
+
+    let mut candidates: Vec<String> = names
+        .iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| name.to_string())
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+// True while the input has more `(` than `)`, meaning the expression continues on the next line
+pub fn is_incomplete(source: &str) -> bool {
+    let opened = source.matches('(').count();
+    let closed = source.matches(')').count();
+    opened > closed
+}
+
+// History is kept in `$XDG_CONFIG_HOME/proton/history`, falling back to
+// `~/.config/proton/history` (`%APPDATA%\proton\history` on Windows)
+pub fn history_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("proton").join("history"))
+}
+
+fn report(err: ProtonError) -> String {
+    format!("error: {}\n", err)
+}
+
+fn parse_source(source: &str) -> Result<Expr, String> {
+    parse(source).map_err(|err| err.render(source))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Split `expr, x` on the last comma outside of any parentheses, so that
+// `:diff max(x, y), x` keeps the call intact
+fn expr_and_variable(source: &str) -> Result<(Expr, String), String> {
+    let mut depth = 0;
+    let mut split = None;
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => split = Some(i),
+            _ => {}
+        }
+    }
+
+    let i = split.ok_or_else(|| "error: expected `expr, variable`\n".to_string())?;
+    let var = source[i + 1..].trim();
+    if !is_identifier(var) {
+        return Err(format!("error: `{}` is not a valid variable name\n", var));
+    }
+    Ok((parse_source(source[..i].trim())?, var.to_string()))
+}
+
+#[cfg(feature = "readline")]
+mod editor {
+    use rustyline::completion::Completer;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+    use rustyline::{Context, Helper};
+
+    // Gives rustyline completion and multi-line input; `names` is refreshed after every line
+    pub struct ReplHelper {
+        pub names: Vec<String>,
+    }
+
+    impl Completer for ReplHelper {
+        type Candidate = String;
+
+        fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+            let names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+            Ok(super::completions(line, pos, &names))
+        }
+    }
+
+    impl Validator for ReplHelper {
+        fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+            if super::is_incomplete(ctx.input()) {
+                Ok(ValidationResult::Incomplete)
+            } else {
+                Ok(ValidationResult::Valid(None))
+            }
+        }
+    }
+
+    impl Hinter for ReplHelper {
+        type Hint = String;
+    }
+
+    impl Highlighter for ReplHelper {}
+
+    impl Helper for ReplHelper {}
+}
+
+// Interactive calculator on stdin and stdout, with line editing, a persistent
+// history and tab completion
+#[cfg(feature = "readline")]
+pub fn repl() {
+    use rustyline::error::ReadlineError;
+    use rustyline::Editor;
+    use rustyline::history::DefaultHistory;
+
+    let mut repl = Repl::new();
+    let mut editor: Editor<editor::ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        // Without a usable terminal, fall back to reading plain lines
+        Err(err) => {
+            eprintln!("warning: line editing unavailable: {}", err);
+            if let Err(err) = repl.run(io::stdin().lock(), io::stdout()) {
+                eprintln!("error: {}", err);
+            }
+            return;
+        }
+    };
+    editor.set_helper(Some(editor::ReplHelper { names: repl.names() }));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session
+        let _ = editor.load_history(path);
+    }
 
-fn repl() {
-    let mut input = String::new();
     loop {
-        print!(">>> ");
-        io::stdout().flush().unwrap();
-        input.clear();
-        io::stdin().read_line(&mut input).unwrap();
-        if input.trim() == "exit" {
+        let line = match editor.readline(">>> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                break;
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        if line == "exit" {
             break;
         }
-        
-        // TODO: parse input into `Expr`
 
-        println!("You entered: {}", input.trim());
+        match repl.execute(line) {
+            Ok(result) => println!("{}", result),
+            Err(message) => print!("{}", message),
+        }
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = repl.names();
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("warning: could not save history to {}: {}", path.display(), err);
+        }
     }
 }
+
+// Interactive calculator on stdin and stdout
+#[cfg(not(feature = "readline"))]
+pub fn repl() {
+    let stdin = io::stdin();
+    Repl::new().run(stdin.lock(), io::stdout()).unwrap();
+}
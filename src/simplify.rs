@@ -1,14 +1,26 @@
+use crate::error::ProtonError;
 use crate::expr::Expr;
+use crate::registry::{builtins, FunctionRegistry};
 
 pub fn simplify(expr: &Expr) -> Expr {
-    match expr {
+    try_simplify(expr).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Like `simplify`, but reports a literal division by zero as an error instead of panicking
+pub fn try_simplify(expr: &Expr) -> Result<Expr, ProtonError> {
+    try_simplify_with(expr, builtins())
+}
+
+// Simplify, folding calls with constant arguments to any function in `functions`
+pub fn try_simplify_with(expr: &Expr, functions: &FunctionRegistry) -> Result<Expr, ProtonError> {
+    let simplified = match expr {
         // If the expression is a number or a variable, return it as it is
         Expr::Number(_) | Expr::Variable(_) => expr.clone(),
 
         // Recursive simplification for add
         Expr::Add(left, right) => {
-            let left: Expr = simplify(&left);
-            let right: Expr = simplify(&right);
+            let left: Expr = try_simplify_with(left, functions)?;
+            let right: Expr = try_simplify_with(right, functions)?;
 
             match (&left, &right) {
                 (Expr::Number(a), Expr::Number(b)) => Expr::Number(a + b),
@@ -19,8 +31,8 @@ pub fn simplify(expr: &Expr) -> Expr {
         },
 
         Expr::Sub(left, right) => {
-            let left: Expr = simplify(&left);
-            let right: Expr = simplify(&right);
+            let left: Expr = try_simplify_with(left, functions)?;
+            let right: Expr = try_simplify_with(right, functions)?;
 
             match (&left, &right) {
                 (Expr::Number(a), Expr::Number(b)) => Expr::Number(a - b),
@@ -30,24 +42,28 @@ pub fn simplify(expr: &Expr) -> Expr {
         },
 
         Expr::Mul(left, right) => {
-            let left: Expr = simplify(&left);
-            let right: Expr = simplify(&right);
+            let left: Expr = try_simplify_with(left, functions)?;
+            let right: Expr = try_simplify_with(right, functions)?;
 
             match (&left, &right) {
                 (Expr::Number(0.0), _) | (_, Expr::Number(0.0)) => Expr::Number(0.0), // 0 * l = r * 0 = 0
                 (Expr::Number(1.0), r) => r.clone(), // 1 * x = x
                 (l, Expr::Number(1.0)) => l.clone(), // x * 1 = x
                 (Expr::Number(a), Expr::Number(b)) => Expr::Number(a * b),
+                // a * (b * r) = (a * b) * r, which keeps repeated derivatives from nesting coefficients
+                (Expr::Number(a), Expr::Mul(inner, r)) if let Expr::Number(b) = **inner => {
+                    Expr::Mul(Box::new(Expr::Number(a * b)), r.clone())
+                }
                 _ => Expr::Mul(Box::new(left), Box::new(right)),
             }
         },
 
         Expr::Div(left, right) => {
-            let left: Expr = simplify(&left);
-            let right: Expr = simplify(&right);
+            let left: Expr = try_simplify_with(left, functions)?;
+            let right: Expr = try_simplify_with(right, functions)?;
         
             match (&left, &right) {
-                (_, Expr::Number(0.0)) => panic!("Cannot divide by zero"),
+                (_, Expr::Number(0.0)) => return Err(ProtonError::DivisionByZero),
                 (Expr::Number(0.0), _) => Expr::Number(0.0), // x / 0 - not possible
                 (l, Expr::Number(1.0)) => l.clone(), // x / 1 = x
                 (Expr::Number(a), Expr::Number(b)) => Expr::Number(a / b),
@@ -56,8 +72,8 @@ pub fn simplify(expr: &Expr) -> Expr {
         },
 
         Expr::Pow(base, power) => {
-            let base: Expr = simplify(&base);
-            let power: Expr = simplify(&power);
+            let base: Expr = try_simplify_with(base, functions)?;
+            let power: Expr = try_simplify_with(power, functions)?;
 
             match (&base, &power) {
                 (_, Expr::Number(0.0)) => Expr::Number(1.0),
@@ -68,74 +84,27 @@ pub fn simplify(expr: &Expr) -> Expr {
         },
 
         Expr::Func(name, args) => {
-            let simplified_args: Vec<Expr> = args.iter().map(|arg| simplify(arg)).collect();
+            let simplified_args: Vec<Expr> = args
+                .iter()
+                .map(|arg| try_simplify_with(arg, functions))
+                .collect::<Result<_, _>>()?;
 
-            // Attempt constant folding for known unary functions
-            match name.as_str() {
-                "sin" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.sin());
-                    }
-                }
-                "cos" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.cos());
-                    }
-                }
-                "tan" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.tan());
-                    }
-                }
-                "ln" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.ln());
-                    }
-                }
-                "log10" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.log10());
-                    }
-                }
-                "sqrt" => {
-                    if let [Expr::Number(n)] = &simplified_args[..] {
-                        return Expr::Number(n.sqrt());
-                    }
-                }
-                "max" => {
-                    if simplified_args.iter().all(|arg| matches!(arg, Expr::Number(_))) {
-                        let max_val = simplified_args
-                            .iter()
-                            .filter_map(|arg| {
-                                if let Expr::Number(n) = arg {
-                                    Some(*n)
-                                } else {
-                                    None
-                                }
-                            })
-                            .fold(f64::NEG_INFINITY, f64::max);
-                        return Expr::Number(max_val);
-                    }
-                }
-                "min" => {
-                    if simplified_args.iter().all(|arg| matches!(arg, Expr::Number(_))) {
-                        let min_val = simplified_args
-                            .iter()
-                            .filter_map(|arg| {
-                                if let Expr::Number(n) = arg {
-                                    Some(*n)
-                                } else {
-                                    None
-                                }
-                            })
-                            .fold(f64::INFINITY, f64::min);
-                        return Expr::Number(min_val);
-                    }
-                }
-                _ => {} // fall through
+            // Attempt constant folding for known functions
+            let values: Option<Vec<f64>> = simplified_args
+                .iter()
+                .map(|arg| match arg {
+                    Expr::Number(n) => Some(*n),
+                    _ => None,
+                })
+                .collect();
+            if let Some(values) = values
+                && let Ok(value) = functions.call(name, &values)
+            {
+                return Ok(Expr::Number(value));
             }
 
             Expr::Func(name.clone(), simplified_args)
         }
-    }
+    };
+    Ok(simplified)
 }
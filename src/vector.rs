@@ -0,0 +1,207 @@
+use crate::differentiate::try_partial_with;
+use crate::error::ProtonError;
+use crate::expr::Expr;
+use crate::registry::{builtins, FunctionRegistry};
+use crate::simplify::try_simplify_with;
+
+// Vector fields are given by their components along the unit vectors of the
+// coordinate system, in the same order as the coordinate names
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateSystem {
+    Cartesian, // (x, y, z, ...) in any dimension
+    Cylindrical, // (r, θ, z)
+    Spherical, // (r, θ, φ) with θ the polar angle from the z axis and φ the azimuth
+}
+
+impl CoordinateSystem {
+    // Scale factors h_i, so that a step dq_i in coordinate i moves h_i * dq_i
+    fn scale_factors(&self, coords: &[&str]) -> Result<Vec<Expr>, ProtonError> {
+        let var = |i: usize| Expr::Variable(coords[i].to_string());
+        let factors = match self {
+            CoordinateSystem::Cartesian => vec![Expr::Number(1.0); coords.len()],
+            CoordinateSystem::Cylindrical => {
+                check_dimension(coords.len(), 3)?;
+                vec![Expr::Number(1.0), var(0), Expr::Number(1.0)]
+            }
+            CoordinateSystem::Spherical => {
+                check_dimension(coords.len(), 3)?;
+                let sin_theta = Expr::Func("sin".to_string(), vec![var(1)]);
+                vec![Expr::Number(1.0), var(0), mul(var(0), sin_theta)]
+            }
+        };
+        Ok(factors)
+    }
+}
+
+fn check_dimension(found: usize, expected: usize) -> Result<(), ProtonError> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(ProtonError::DimensionMismatch { expected, found })
+    }
+}
+
+fn mul(left: Expr, right: Expr) -> Expr {
+    Expr::Mul(Box::new(left), Box::new(right))
+}
+
+fn div(left: Expr, right: Expr) -> Expr {
+    Expr::Div(Box::new(left), Box::new(right))
+}
+
+fn sum(terms: Vec<Expr>) -> Expr {
+    terms
+        .into_iter()
+        .reduce(|sum, term| Expr::Add(Box::new(sum), Box::new(term)))
+        .unwrap_or(Expr::Number(0.0))
+}
+
+// Product of the scale factors, the volume element h1 * h2 * h3
+fn volume(factors: &[Expr]) -> Expr {
+    factors.iter().cloned().reduce(mul).unwrap_or(Expr::Number(1.0))
+}
+
+// ∇f, with components (1 / h_i) * ∂f/∂q_i
+pub fn gradient(f: &Expr, coords: &[&str], system: CoordinateSystem) -> Vec<Expr> {
+    try_gradient(f, coords, system).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_gradient(f: &Expr, coords: &[&str], system: CoordinateSystem) -> Result<Vec<Expr>, ProtonError> {
+    try_gradient_with(f, coords, system, builtins())
+}
+
+pub fn try_gradient_with(
+    f: &Expr,
+    coords: &[&str],
+    system: CoordinateSystem,
+    functions: &FunctionRegistry,
+) -> Result<Vec<Expr>, ProtonError> {
+    let factors = system.scale_factors(coords)?;
+    coords
+        .iter()
+        .zip(factors)
+        .map(|(q, h)| {
+            let partial = try_partial_with(f, &[(q, 1)], functions)?;
+            try_simplify_with(&div(partial, h), functions)
+        })
+        .collect()
+}
+
+// ∇·F = 1 / (h1 h2 h3) * Σ ∂/∂q_i (h1 h2 h3 / h_i * F_i)
+pub fn divergence(field: &[Expr], coords: &[&str], system: CoordinateSystem) -> Expr {
+    try_divergence(field, coords, system).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_divergence(field: &[Expr], coords: &[&str], system: CoordinateSystem) -> Result<Expr, ProtonError> {
+    try_divergence_with(field, coords, system, builtins())
+}
+
+pub fn try_divergence_with(
+    field: &[Expr],
+    coords: &[&str],
+    system: CoordinateSystem,
+    functions: &FunctionRegistry,
+) -> Result<Expr, ProtonError> {
+    check_dimension(field.len(), coords.len())?;
+    let factors = system.scale_factors(coords)?;
+    let volume = volume(&factors);
+
+    let mut terms = Vec::new();
+    for (i, component) in field.iter().enumerate() {
+        let flux = try_simplify_with(&mul(div(volume.clone(), factors[i].clone()), component.clone()), functions)?;
+        terms.push(try_partial_with(&flux, &[(coords[i], 1)], functions)?);
+    }
+    try_simplify_with(&div(sum(terms), volume), functions)
+}
+
+// ∇×F in three dimensions, with components
+// (∇×F)_i = h_i / (h1 h2 h3) * (∂(h_k F_k)/∂q_j - ∂(h_j F_j)/∂q_k) for (i, j, k) cyclic
+pub fn curl(field: &[Expr], coords: &[&str], system: CoordinateSystem) -> Vec<Expr> {
+    try_curl(field, coords, system).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_curl(field: &[Expr], coords: &[&str], system: CoordinateSystem) -> Result<Vec<Expr>, ProtonError> {
+    try_curl_with(field, coords, system, builtins())
+}
+
+pub fn try_curl_with(
+    field: &[Expr],
+    coords: &[&str],
+    system: CoordinateSystem,
+    functions: &FunctionRegistry,
+) -> Result<Vec<Expr>, ProtonError> {
+    check_dimension(coords.len(), 3)?;
+    check_dimension(field.len(), 3)?;
+    let factors = system.scale_factors(coords)?;
+    let volume = volume(&factors);
+    let scaled: Vec<Expr> = field
+        .iter()
+        .zip(&factors)
+        .map(|(component, h)| try_simplify_with(&mul(h.clone(), component.clone()), functions))
+        .collect::<Result<_, _>>()?;
+
+    (0..3)
+        .map(|i| {
+            let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+            let rotation = Expr::Sub(
+                Box::new(try_partial_with(&scaled[k], &[(coords[j], 1)], functions)?),
+                Box::new(try_partial_with(&scaled[j], &[(coords[k], 1)], functions)?),
+            );
+            try_simplify_with(&mul(div(factors[i].clone(), volume.clone()), rotation), functions)
+        })
+        .collect()
+}
+
+// ∇²f = 1 / (h1 h2 h3) * Σ ∂/∂q_i (h1 h2 h3 / h_i^2 * ∂f/∂q_i)
+pub fn laplacian(f: &Expr, coords: &[&str], system: CoordinateSystem) -> Expr {
+    try_laplacian(f, coords, system).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_laplacian(f: &Expr, coords: &[&str], system: CoordinateSystem) -> Result<Expr, ProtonError> {
+    try_laplacian_with(f, coords, system, builtins())
+}
+
+pub fn try_laplacian_with(
+    f: &Expr,
+    coords: &[&str],
+    system: CoordinateSystem,
+    functions: &FunctionRegistry,
+) -> Result<Expr, ProtonError> {
+    // ∇²f = ∇·(∇f)
+    let gradient = try_gradient_with(f, coords, system, functions)?;
+    try_divergence_with(&gradient, coords, system, functions)
+}
+
+// The rate of change of f along `direction`, ∇f · u / |u|; the direction
+// need not be a unit vector and may depend on the coordinates
+pub fn directional_derivative(f: &Expr, direction: &[Expr], coords: &[&str], system: CoordinateSystem) -> Expr {
+    try_directional_derivative(f, direction, coords, system).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_directional_derivative(
+    f: &Expr,
+    direction: &[Expr],
+    coords: &[&str],
+    system: CoordinateSystem,
+) -> Result<Expr, ProtonError> {
+    try_directional_derivative_with(f, direction, coords, system, builtins())
+}
+
+pub fn try_directional_derivative_with(
+    f: &Expr,
+    direction: &[Expr],
+    coords: &[&str],
+    system: CoordinateSystem,
+    functions: &FunctionRegistry,
+) -> Result<Expr, ProtonError> {
+    check_dimension(direction.len(), coords.len())?;
+    let gradient = try_gradient_with(f, coords, system, functions)?;
+
+    let dot = sum(gradient.into_iter().zip(direction).map(|(g, u)| mul(g, u.clone())).collect());
+    let squares = sum(direction
+        .iter()
+        .map(|u| Expr::Pow(Box::new(u.clone()), Box::new(Expr::Number(2.0))))
+        .collect());
+    let length = Expr::Func("sqrt".to_string(), vec![squares]);
+    try_simplify_with(&div(dot, length), functions)
+}
@@ -4,6 +4,24 @@ use proton_lite::eval::evaluate;
 use proton_lite::differentiate::differentiate;
 use proton_lite::integrate::integrate;
 
+// `expr` at the point (x, y)
+fn at(expr: &Expr, x: f64, y: f64) -> f64 {
+    evaluate(expr, &HashMap::from([("x".to_string(), x), ("y".to_string(), y)]))
+}
+
+// Differentiating the antiderivative of `source` in x must give it back at
+// each of `points`, with `a` bound to 1.5 for integrands with a parameter
+fn assert_antiderivative(source: &str, points: &[f64]) {
+    let expr = proton_lite::parse::parse(source).unwrap();
+    let integral = proton_lite::integrate::try_integrate(&expr, "x").unwrap_or_else(|err| panic!("{}: {}", source, err));
+    let derivative = differentiate(&integral, "x");
+    for &x in points {
+        let vars = HashMap::from([("x".to_string(), x), ("a".to_string(), 1.5)]);
+        let (expected, found) = (evaluate(&expr, &vars), evaluate(&derivative, &vars));
+        assert!((expected - found).abs() < 1e-9 * (1.0 + expected.abs()), "{} at {}: {} vs {}", source, x, found, expected);
+    }
+}
+
 #[test]
 fn test_addition() {
     let expr = Expr::Add(
@@ -322,8 +340,9 @@ fn test_integrate_pow() {
 fn test_integrate_inv() {
     let expr = Expr::Pow(Box::new(Expr::Variable("x".to_string())), Box::new(Expr::Number(-1.0)));
     let result = integrate(&expr, "x");
-    // ∫x^-1 dx = ln(x)
-    assert_eq!(result, Expr::Func("ln".to_string(), vec![Expr::Variable("x".to_string())]));
+    // ∫x^-1 dx = ln|x|
+    let abs = Expr::Func("abs".to_string(), vec![Expr::Variable("x".to_string())]);
+    assert_eq!(result, Expr::Func("ln".to_string(), vec![abs]));
 }
 
 #[test]
@@ -358,3 +377,1539 @@ fn test_integrate_ln() {
         Box::new(Expr::Variable("x".to_string()))
     ));
 }
+
+#[cfg(test)]
+mod parse_tests {
+    use super::*;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::parse;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    fn num(n: f64) -> Box<Expr> {
+        Box::new(Expr::Number(n))
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        // 1 + 2 * x ^ 2 = 1 + (2 * (x ^ 2))
+        let expr = parse("1 + 2 * x ^ 2").unwrap();
+        assert_eq!(expr, Expr::Add(
+            num(1.0),
+            Box::new(Expr::Mul(num(2.0), Box::new(Expr::Pow(var("x"), num(2.0))))),
+        ));
+    }
+
+    #[test]
+    fn test_parse_left_and_right_associativity() {
+        // a - b - c = (a - b) - c
+        let expr = parse("a - b - c").unwrap();
+        assert_eq!(expr, Expr::Sub(Box::new(Expr::Sub(var("a"), var("b"))), var("c")));
+
+        // a ^ b ^ c = a ^ (b ^ c)
+        let expr = parse("a ^ b ^ c").unwrap();
+        assert_eq!(expr, Expr::Pow(var("a"), Box::new(Expr::Pow(var("b"), var("c")))));
+    }
+
+    #[test]
+    fn test_parse_numbers_and_unary_minus() {
+        assert_eq!(parse("2.5e-3").unwrap(), Expr::Number(2.5e-3));
+        assert_eq!(parse("1E6").unwrap(), Expr::Number(1e6));
+        // -x is -1 * x, and binds looser than ^
+        assert_eq!(parse("-x ^ 2").unwrap(), Expr::Mul(num(-1.0), Box::new(Expr::Pow(var("x"), num(2.0)))));
+        assert_eq!(parse("x * -3").unwrap(), Expr::Mul(var("x"), num(-3.0)));
+        // ... whether or not the operand is a literal
+        assert_eq!(parse("-2 ^ 2").unwrap(), Expr::Mul(num(-1.0), Box::new(Expr::Pow(num(2.0), num(2.0)))));
+        assert_eq!(evaluate(&parse("- 2 ^ 2").unwrap(), &HashMap::new()), -4.0);
+        assert_eq!(evaluate(&parse("-2 ^ 0.5").unwrap(), &HashMap::new()), -2.0_f64.sqrt());
+        assert_eq!(evaluate(&parse("(-2) ^ 2").unwrap(), &HashMap::new()), 4.0);
+        assert_eq!(to_string(&Expr::Pow(num(-2.0), num(2.0))), "((-2) ^ 2)");
+    }
+
+    #[test]
+    fn test_parse_function_calls() {
+        let expr = parse("max(x, 2, sin(y))").unwrap();
+        assert_eq!(expr, Expr::Func(
+            "max".to_string(),
+            vec![
+                Expr::Variable("x".to_string()),
+                Expr::Number(2.0),
+                Expr::Func("sin".to_string(), vec![Expr::Variable("y".to_string())]),
+            ],
+        ));
+        let vars = HashMap::from([("y".to_string(), 2.0)]);
+        assert_eq!(evaluate(&parse("ln(y) / ln(2)").unwrap(), &vars), 1.0);
+    }
+
+    #[test]
+    fn test_parse_inverts_to_string() {
+        let exprs = vec![
+            Expr::Sub(num(-2.0), Box::new(Expr::Pow(num(-2.0), num(0.5)))),
+            Expr::Div(Box::new(Expr::Mul(var("θ"), num(1e-7))), var("x_1")),
+            Expr::Pow(var("x"), Box::new(Expr::Pow(var("y"), num(-1.0)))),
+            Expr::Func("min".to_string(), vec![Expr::Number(f64::INFINITY), Expr::Number(-0.0)]),
+            differentiate(&Expr::Pow(var("x"), var("x")), "x"),
+        ];
+        for expr in exprs {
+            assert_eq!(parse(&to_string(&expr)).unwrap(), expr);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("(x + 1").is_err());
+        assert!(parse("x +").is_err());
+        assert!(parse("2 $ 3").is_err());
+        assert_eq!(parse("x y").unwrap_err().span, 2..3);
+    }
+
+    #[test]
+    fn test_parse_error_spans() {
+        // Unbalanced parentheses point at the unclosed `(` or the stray `)`
+        let err = parse("max(x, 2").unwrap_err();
+        assert_eq!(err.message, "unclosed `(`");
+        assert_eq!(err.span, 3..8);
+        assert_eq!(err.expected, vec!["`,`", "`)`"]);
+        assert_eq!(parse("x + 1)").unwrap_err().message, "unmatched `)`");
+
+        // Dangling operators point at the operator
+        let err = parse("x * (y +)").unwrap_err();
+        assert_eq!(err.span, 7..8);
+        assert_eq!(err.message, "operator `+` is missing its right operand");
+
+        // Unknown characters cover the whole (possibly multi-byte) character
+        let err = parse("θ € 2").unwrap_err();
+        assert_eq!(err.span, 3..6);
+
+        // Empty argument lists and empty arguments
+        assert_eq!(parse("sin()").unwrap_err().span, 0..5);
+        assert_eq!(parse("max(x, )").unwrap_err().message, "empty argument in call to `max`");
+    }
+
+    #[test]
+    fn test_parse_error_render() {
+        let err = parse("max(x, 2").unwrap_err();
+        assert_eq!(
+            err.render("max(x, 2"),
+            "error: unclosed `(`\n  |\n1 | max(x, 2\n  |    ^~~~~\n  = expected `,` or `)`\n"
+        );
+
+        // Errors on later lines render that line, with the caret under the operator missing its operand
+        let source = "1 +\n2 *";
+        let rendered = parse(source).unwrap_err().render(source);
+        assert!(rendered.contains("2 | 2 *\n  |   ^\n"));
+    }
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use proton_lite::repl::{is_incomplete, Repl};
+
+    fn run(input: &str) -> (Repl, String) {
+        let mut repl = Repl::new();
+        let mut output = Vec::new();
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        (repl, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_repl_evaluates_lines() {
+        let (_, output) = run("1 + 2 * 3\nexit\n2 + 2\n");
+        // Stops at `exit`, so the last line is never evaluated
+        assert_eq!(output, ">>> 7\n>>> ");
+    }
+
+    #[test]
+    fn test_repl_let_bindings_persist() {
+        let (repl, output) = run("let x = 3\nlet y = x ^ 2\nx + y\n");
+        assert_eq!(output, ">>> x = 3\n>>> y = 9\n>>> 12\n>>> \n");
+        assert_eq!(repl.vars["y"], 9.0);
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.execute(":diff x ^ 3, x").unwrap(), "(3 * (x ^ 2))");
+        assert_eq!(repl.execute(":diff x * sin(y), y").unwrap(), "(x * cos(y))");
+        assert_eq!(repl.execute(":int cos(x), x").unwrap(), "sin(x)");
+        assert_eq!(repl.execute(":simplify x * 1 + 0").unwrap(), "x");
+    }
+
+    #[test]
+    fn test_repl_reports_errors_and_continues() {
+        let (_, output) = run("2 +\n:frobnicate\n4\n");
+        assert!(output.contains("error: operator `+` is missing its right operand\n"));
+        assert!(output.contains("error: unknown command `:frobnicate`"));
+        assert!(output.ends_with(">>> 4\n>>> \n"));
+    }
+
+    #[test]
+    fn test_repl_continues_unbalanced_lines() {
+        assert!(is_incomplete("max(x,"));
+        assert!(!is_incomplete("max(x, 2)"));
+
+        let (_, output) = run("max(1,\n  sin(0),\n  5) + 1\n");
+        assert_eq!(output, ">>> ... ... 6\n>>> \n");
+    }
+
+    #[test]
+    fn test_repl_tab_completion() {
+        let mut repl = Repl::new();
+        repl.execute("let sigma = 2").unwrap();
+
+        // Functions and bound variables, starting at the word under the cursor
+        assert_eq!(repl.complete("1 + sig", 7), (4, vec!["sigma".to_string(), "sign".to_string()]));
+        assert_eq!(repl.complete("log", 3), (0, vec!["log".to_string(), "log10".to_string()]));
+        assert_eq!(repl.complete("2 + 3", 5), (5, vec![]));
+
+        // Command names after a leading `:`
+        assert_eq!(repl.complete(":si", 3), (0, vec![":simplify".to_string()]));
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use proton_lite::expr::to_latex;
+    use proton_lite::parse::parse;
+    use std::process::{Command, Output};
+
+    fn proton(args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_proton")).args(args).output().unwrap()
+    }
+
+    fn stdout(output: &Output) -> String {
+        String::from_utf8(output.stdout.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_to_latex() {
+        let latex = |source: &str| to_latex(&parse(source).unwrap());
+        assert_eq!(latex("(x + 1) / y ^ 2"), "\\frac{x + 1}{y^{2}}");
+        assert_eq!(latex("a - (b + c) * -2"), "a - \\left(b + c\\right) \\cdot \\left(-2\\right)");
+        assert_eq!(latex("sqrt(theta) ^ (x + 1)"), "\\sqrt{\\mathrm{theta}}^{x + 1}");
+        assert_eq!(latex("log10(x) + f(x, y)"), "\\log_{10}\\left(x\\right) + \\operatorname{f}\\left(x, y\\right)");
+    }
+
+    #[test]
+    fn test_cli_commands() {
+        let output = proton(&["eval", "x^2 + 1", "--var", "x=3"]);
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "10\n");
+
+        assert_eq!(stdout(&proton(&["diff", "sin(x) * x", "x"])), "((cos(x) * x) + sin(x))\n");
+        assert_eq!(stdout(&proton(&["integrate", "x^2", "x", "--format", "latex"])), "\\frac{x^{3}}{3}\n");
+        assert_eq!(
+            stdout(&proton(&["simplify", "x * 1 + 0", "--format=json"])),
+            "{\"result\": \"x\", \"latex\": \"x\"}\n"
+        );
+        assert_eq!(stdout(&proton(&["eval", "2 * y", "--var=y=4", "--format", "json"])), "{\"result\": 8}\n");
+    }
+
+    #[test]
+    fn test_cli_exit_codes() {
+        // Parse errors and unbound variables fail with 1 and a message on stderr
+        let output = proton(&["eval", "2 +"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr).unwrap().contains("missing its right operand"));
+        assert_eq!(proton(&["eval", "x + 1"]).status.code(), Some(1));
+        assert_eq!(proton(&["eval", "nope(1)"]).status.code(), Some(1));
+        assert_eq!(proton(&["integrate", "x ^ y", "x"]).status.code(), Some(1));
+        // NaN results fail like NaN rows in batch, infinities don't
+        for source in ["0 / 0", "sqrt(-1)"] {
+            let output = proton(&["eval", source]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(String::from_utf8(output.stderr).unwrap().contains("result is not a number"));
+        }
+        assert_eq!(stdout(&proton(&["eval", "1 / 0", "--format", "json"])), "{\"result\": null}\n");
+
+        // Usage errors fail with 2
+        assert_eq!(proton(&[]).status.code(), Some(2));
+        assert_eq!(proton(&["diff", "x"]).status.code(), Some(2));
+        assert_eq!(proton(&["eval", "x", "--format", "xml"]).status.code(), Some(2));
+    }
+
+    #[test]
+    fn test_cli_batch() {
+        let dir = std::env::temp_dir().join(format!("proton-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.csv");
+        let output = dir.join("out.csv");
+        std::fs::write(&input, "r,h\n1,2\n2,x\n").unwrap();
+
+        let result = proton(&[
+            "batch", "r ^ 2 * h", input.to_str().unwrap(), "--output", output.to_str().unwrap(), "--column", "volume",
+        ]);
+        // The failing row is reported and sets the exit code, the good rows are still written
+        assert_eq!(result.status.code(), Some(1));
+        assert!(String::from_utf8(result.stderr).unwrap().contains("line 3: `x` in column `h` is not a number"));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "r,h,volume,error\n1,2,2,\n2,x,,`x` in column `h` is not a number\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use proton_lite::batch::{evaluate_csv, BatchError, RowError};
+    use proton_lite::parse::parse;
+
+    fn run(expr: &str, csv: &str) -> (String, Vec<RowError>) {
+        let mut output = Vec::new();
+        let summary = evaluate_csv(&parse(expr).unwrap(), csv.as_bytes(), &mut output, "result").unwrap();
+        (String::from_utf8(output).unwrap(), summary.errors)
+    }
+
+    #[test]
+    fn test_batch_appends_result_column() {
+        let (output, errors) = run("x * y + 1", "x,y,label\n1,2,a\n\"3\",4,\"b, c\"\n");
+        assert_eq!(output, "x,y,label,result,error\n1,2,a,3,\n3,4,\"b, c\",13,\n");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_batch_reports_row_errors_and_continues() {
+        let (output, errors) = run("x ^ 2", "x,note\nfoo,a\n2\n3,b\n");
+        assert_eq!(output, "x,note,result,error\nfoo,a,,`foo` in column `x` is not a number\n2,,,\"expected 2 fields, found 1\"\n3,b,9,\n");
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_batch_reads_back_its_own_output() {
+        let (output, errors) = run("x + 1", "x,note\n1,\"first\nsecond\"\n2,plain\n");
+        assert_eq!(output, "x,note,result,error\n1,\"first\nsecond\",2,\n2,plain,3,\n");
+        assert!(errors.is_empty());
+
+        // Rows still number by the line they start on
+        let (output, errors) = run("x * 2", &output.replace("2,plain", "foo,plain"));
+        assert!(output.contains("1,\"first\nsecond\",2,,2,\n"));
+        assert_eq!(errors, vec![RowError { line: 4, message: "`foo` in column `x` is not a number".to_string() }]);
+    }
+
+    #[test]
+    fn test_batch_reports_nan_results() {
+        let (output, errors) = run("sqrt(x)", "x\n-1\n4\n");
+        assert_eq!(output, "x,result,error\n-1,,result is not a number\n4,2,\n");
+        assert_eq!(errors, vec![RowError { line: 2, message: "result is not a number".to_string() }]);
+    }
+
+    #[test]
+    fn test_batch_requires_columns_for_every_variable() {
+        let mut output = Vec::new();
+        let err = evaluate_csv(&parse("x + z").unwrap(), "x,y\n1,2\n".as_bytes(), &mut output, "result").unwrap_err();
+        assert!(matches!(err, BatchError::MissingColumns(ref names) if names == &vec!["z".to_string()]));
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+    use proton_lite::differentiate::try_differentiate;
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::try_evaluate;
+    use proton_lite::integrate::try_integrate;
+    use proton_lite::parse::parse;
+    use proton_lite::registry::Arity;
+    use proton_lite::simplify::try_simplify;
+
+    #[test]
+    fn test_try_evaluate_errors() {
+        let vars = HashMap::from([("x".to_string(), 1.0)]);
+        assert_eq!(try_evaluate(&parse("x + 1").unwrap(), &vars), Ok(2.0));
+        assert_eq!(
+            try_evaluate(&parse("x + y").unwrap(), &vars),
+            Err(ProtonError::UnboundVariables(vec!["y".to_string()]))
+        );
+        assert_eq!(
+            try_evaluate(&parse("sin(x, 2)").unwrap(), &vars),
+            Err(ProtonError::ArityMismatch { name: "sin".to_string(), expected: Arity::Exact(1), found: 2 })
+        );
+        assert_eq!(
+            try_evaluate(&parse("gamma(x)").unwrap(), &vars),
+            Err(ProtonError::UnknownFunction("gamma".to_string()))
+        );
+        // Division by zero follows IEEE semantics
+        assert_eq!(try_evaluate(&parse("1 / (x - 1)").unwrap(), &vars), Ok(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_try_differentiate_errors() {
+        assert_eq!(
+            try_differentiate(&parse("gamma(x)").unwrap(), "x"),
+            Err(ProtonError::UnknownFunction("gamma".to_string()))
+        );
+        // Errors deep inside the tree propagate out
+        assert!(try_differentiate(&parse("x * (1 + gamma(x))").unwrap(), "x").is_err());
+    }
+
+    #[test]
+    fn test_try_integrate_errors() {
+        assert_eq!(
+            try_integrate(&parse("x ^ y").unwrap(), "x"),
+            Err(ProtonError::NoClosedForm("(x ^ y)".to_string()))
+        );
+        assert!(matches!(
+            try_integrate(&parse("max(x)").unwrap(), "x"),
+            Err(ProtonError::UnsupportedFunction { ref name, operation: "integrate" }) if name == "max"
+        ));
+    }
+
+    #[test]
+    fn test_try_simplify_division_by_zero() {
+        assert_eq!(try_simplify(&parse("x / (1 - 1)").unwrap()), Err(ProtonError::DivisionByZero));
+        assert_eq!(
+            ProtonError::DivisionByZero.to_string(),
+            "cannot divide by zero"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot divide by zero")]
+    fn test_simplify_still_panics() {
+        proton_lite::simplify::simplify(&parse("x / 0").unwrap());
+    }
+}
+
+#[cfg(test)]
+mod eval_context_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::{EvalContext, UnboundPolicy};
+    use proton_lite::parse::parse;
+
+    #[test]
+    fn test_strict_policy_reports_every_missing_variable() {
+        let vars = HashMap::from([("y".to_string(), 1.0)]);
+        let expr = parse("x * y + sin(z) - x").unwrap();
+        assert_eq!(
+            EvalContext::new(&vars).evaluate(&expr),
+            Err(ProtonError::UnboundVariables(vec!["x".to_string(), "z".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_nan_and_default_policies() {
+        let vars = HashMap::from([("y".to_string(), 2.0)]);
+        let expr = parse("x + y").unwrap();
+
+        let nan = EvalContext::new(&vars).with_policy(UnboundPolicy::Nan).evaluate(&expr);
+        assert!(nan.unwrap().is_nan());
+
+        let default = EvalContext::new(&vars).with_policy(UnboundPolicy::Default(0.5)).evaluate(&expr);
+        assert_eq!(default, Ok(2.5));
+
+        // `evaluate` keeps returning NaN for unbound variables
+        assert!(evaluate(&expr, &vars).is_nan());
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use super::*;
+    use proton_lite::differentiate::try_differentiate_with;
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::EvalContext;
+    use proton_lite::expr::to_string;
+    use proton_lite::integrate::try_integrate_with;
+    use proton_lite::parse::parse;
+    use proton_lite::registry::{Arity, Function, FunctionRegistry};
+    use proton_lite::simplify::{simplify, try_simplify_with};
+
+    fn with_sigmoid() -> FunctionRegistry {
+        let mut registry = FunctionRegistry::default();
+        registry.register(
+            Function::unary("sigmoid", |x| 1.0 / (1.0 + (-x).exp()))
+                // sigmoid'(u) = sigmoid(u) * (1 - sigmoid(u)) * u'
+                .with_derivative(|u, du| {
+                    let s = Expr::Func("sigmoid".to_string(), vec![u[0].clone()]);
+                    Expr::Mul(
+                        Box::new(Expr::Mul(
+                            Box::new(s.clone()),
+                            Box::new(Expr::Sub(Box::new(Expr::Number(1.0)), Box::new(s))),
+                        )),
+                        Box::new(du[0].clone()),
+                    )
+                }),
+        );
+        registry
+    }
+
+    #[test]
+    fn test_registered_function_in_every_pass() {
+        let registry = with_sigmoid();
+        let vars = HashMap::from([("x".to_string(), 0.0)]);
+        let expr = parse("sigmoid(2 * x)").unwrap();
+
+        let value = EvalContext::new(&vars).with_functions(&registry).evaluate(&expr);
+        assert_eq!(value, Ok(0.5));
+
+        let d = try_differentiate_with(&expr, "x", &registry).unwrap();
+        let d = try_simplify_with(&d, &registry).unwrap();
+        assert_eq!(to_string(&d), "((sigmoid((2 * x)) * (1 - sigmoid((2 * x)))) * 2)");
+        let slope = EvalContext::new(&vars).with_functions(&registry).evaluate(&d);
+        assert_eq!(slope, Ok(0.5));
+
+        // Constant calls fold during simplification
+        assert_eq!(try_simplify_with(&parse("sigmoid(0) + 1").unwrap(), &registry), Ok(Expr::Number(1.5)));
+
+        // No antiderivative was registered
+        assert!(matches!(
+            try_integrate_with(&expr, "x", &registry),
+            Err(ProtonError::UnsupportedFunction { operation: "integrate", .. })
+        ));
+    }
+
+    #[test]
+    fn test_registry_checks_arity() {
+        let mut registry = FunctionRegistry::empty();
+        registry.register(Function::new("hypot", Arity::Exact(2), |args| args[0].hypot(args[1])));
+        let vars = HashMap::new();
+
+        let ok = EvalContext::new(&vars).with_functions(&registry).evaluate(&parse("hypot(3, 4)").unwrap());
+        assert_eq!(ok, Ok(5.0));
+        let err = EvalContext::new(&vars).with_functions(&registry).evaluate(&parse("hypot(3)").unwrap());
+        assert_eq!(err, Err(ProtonError::ArityMismatch { name: "hypot".to_string(), expected: Arity::Exact(2), found: 1 }));
+        assert_eq!(err.unwrap_err().to_string(), "hypot() expects 2 argument(s), found 1");
+
+        // The empty registry doesn't know the built-ins
+        let err = EvalContext::new(&vars).with_functions(&registry).evaluate(&parse("sin(1)").unwrap());
+        assert_eq!(err, Err(ProtonError::UnknownFunction("sin".to_string())));
+    }
+
+    #[test]
+    fn test_builtins_agree_across_passes() {
+        // log10 can now be differentiated, tan and sqrt integrated
+        let d = simplify(&differentiate(&parse("log10(x)").unwrap(), "x"));
+        assert_eq!(to_string(&d), "(1 / (x * 2.302585092994046))");
+        assert_eq!(to_string(&integrate(&parse("tan(x)").unwrap(), "x")), "(-1 * ln(cos(x)))");
+        assert_eq!(to_string(&integrate(&parse("sqrt(x)").unwrap(), "x")), "((2 * (x ^ 1.5)) / 3)");
+    }
+}
+
+#[cfg(test)]
+mod user_function_tests {
+    use super::*;
+    use proton_lite::differentiate::try_differentiate_with;
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::EvalContext;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::{parse, parse_definition};
+    use proton_lite::registry::FunctionRegistry;
+    use proton_lite::repl::Repl;
+    use proton_lite::simplify::try_simplify_with;
+
+    fn define(registry: &mut FunctionRegistry, source: &str) -> Result<(), ProtonError> {
+        let definition = parse_definition(source)?;
+        let params: Vec<&str> = definition.params.iter().map(String::as_str).collect();
+        registry.define(&definition.name, &params, definition.body)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_and_expand() {
+        let mut registry = FunctionRegistry::default();
+        define(&mut registry, "f(x, y) = x ^ 2 + y").unwrap();
+        define(&mut registry, "g(t) = f(t, 1) * 2").unwrap();
+
+        let expr = parse("f(3, t) + g(t)").unwrap();
+        let vars = HashMap::from([("t".to_string(), 2.0)]);
+        let value = EvalContext::new(&vars).with_functions(&registry).evaluate(&expr);
+        assert_eq!(value, Ok(11.0 + 10.0));
+
+        // The call is kept until it is expanded, with the arguments substituted
+        assert_eq!(to_string(&expr), "(f(3, t) + g(t))");
+        assert_eq!(
+            to_string(&registry.expand(&expr)),
+            "(((3 ^ 2) + t) + (((t ^ 2) + 1) * 2))"
+        );
+    }
+
+    #[test]
+    fn test_differentiate_through_definition() {
+        let mut registry = FunctionRegistry::default();
+        define(&mut registry, "f(x, y) = x ^ 2 + y").unwrap();
+
+        // The parameter `x` is not the variable `x` of the outer expression
+        let expr = parse("f(y, x)").unwrap();
+        let d = try_differentiate_with(&expr, "x", &registry).unwrap();
+        assert_eq!(try_simplify_with(&d, &registry), Ok(Expr::Number(1.0)));
+    }
+
+    #[test]
+    fn test_recursive_definitions_are_rejected() {
+        let mut registry = FunctionRegistry::default();
+        assert_eq!(
+            define(&mut registry, "f(x) = f(x - 1) + 1"),
+            Err(ProtonError::RecursiveDefinition("f".to_string()))
+        );
+
+        // Also through another definition, without replacing the old `f`
+        define(&mut registry, "f(x) = x + 1").unwrap();
+        define(&mut registry, "g(x) = f(x) * 2").unwrap();
+        assert_eq!(
+            define(&mut registry, "f(x) = g(x)"),
+            Err(ProtonError::RecursiveDefinition("f".to_string()))
+        );
+        assert_eq!(registry.call("g", &[1.0]), Ok(4.0));
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let mut registry = FunctionRegistry::default();
+        assert!(matches!(define(&mut registry, "f(x) = x + y"), Err(ProtonError::InvalidDefinition(_))));
+        assert!(matches!(define(&mut registry, "f(x, x) = x"), Err(ProtonError::InvalidDefinition(_))));
+        assert!(matches!(define(&mut registry, "sin(x) = x"), Err(ProtonError::InvalidDefinition(_))));
+        assert!(matches!(define(&mut registry, "f(x) = nope(x)"), Err(ProtonError::UnknownFunction(_))));
+        assert!(matches!(define(&mut registry, "f(2) = 2"), Err(ProtonError::Parse(_))));
+
+        // Errors in the body point into the whole line
+        let err = parse_definition("f(x) = x +").unwrap_err();
+        assert_eq!(err.span, 9..10);
+    }
+
+    #[test]
+    fn test_repl_definitions() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.execute("f(x, y) = x^2 + y"), Ok("f(x, y) = ((x ^ 2) + y)".to_string()));
+        assert_eq!(repl.execute("f(3, 1)"), Ok("10".to_string()));
+        assert_eq!(repl.execute(":expand f(a, 2)"), Ok("((a ^ 2) + 2)".to_string()));
+        assert_eq!(repl.execute(":diff f(a, a), a"), Ok("((2 * a) + 1)".to_string()));
+        assert!(repl.execute("h(x) = h(x)").unwrap_err().contains("in terms of itself"));
+        assert!(repl.complete("f", 1).1.contains(&"f".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod elementary_derivative_tests {
+    use super::*;
+    use proton_lite::expr::{to_latex, to_string};
+    use proton_lite::parse::parse;
+    use proton_lite::simplify::simplify;
+
+    // Compare the symbolic derivative with a central difference at `x`
+    fn assert_derivative_matches(source: &str, x: f64) {
+        let expr = parse(source).unwrap();
+        let d = differentiate(&expr, "x");
+        let at = |x: f64| evaluate(&expr, &HashMap::from([("x".to_string(), x)]));
+        let h = 1e-6;
+        let numeric = (at(x + h) - at(x - h)) / (2.0 * h);
+        let symbolic = evaluate(&d, &HashMap::from([("x".to_string(), x)]));
+        assert!(
+            (numeric - symbolic).abs() < 1e-5 * (1.0 + numeric.abs()),
+            "d/dx {} at {}: symbolic {} ({}), numeric {}",
+            source, x, symbolic, to_string(&d), numeric
+        );
+    }
+
+    #[test]
+    fn test_every_builtin_has_a_derivative() {
+        let cases = [
+            "exp(2 * x)", "asin(x / 2)", "acos(x / 2)", "atan(x ^ 2)",
+            "sinh(x)", "cosh(3 * x)", "tanh(x)", "abs(x - 2)", "sec(x)",
+            "csc(x)", "cot(x)", "log(2, x ^ 2)", "log(x, 8)", "log10(x)",
+            "max(x, 1)", "min(x, 1)", "max(x, 2 * x, 0.2)", "min(sin(x), cos(x))",
+            "sign(x) * x", "sqrt(x) * ln(x) + tan(x)",
+        ];
+        for source in cases {
+            assert_derivative_matches(source, 0.7);
+            assert_derivative_matches(source, 1.3);
+        }
+    }
+
+    #[test]
+    fn test_piecewise_derivatives() {
+        let d = |source: &str| to_string(&simplify(&differentiate(&parse(source).unwrap(), "x")));
+        assert_eq!(d("abs(x)"), "sign(x)");
+        assert_eq!(d("exp(x)"), "exp(x)");
+        assert_eq!(evaluate(&parse("sign(0) + sign(-3)").unwrap(), &HashMap::new()), -1.0);
+
+        // max picks the derivative of whichever argument is larger
+        let dmax = differentiate(&parse("max(x, 3 * x)").unwrap(), "x");
+        assert_eq!(evaluate(&dmax, &HashMap::from([("x".to_string(), 1.0)])), 3.0);
+        assert_eq!(evaluate(&dmax, &HashMap::from([("x".to_string(), -1.0)])), 1.0);
+    }
+
+    #[test]
+    fn test_elementary_latex() {
+        assert_eq!(to_latex(&parse("log(2, x)").unwrap()), "\\log_{2}\\left(x\\right)");
+        assert_eq!(to_latex(&parse("abs(x)").unwrap()), "\\left|x\\right|");
+        assert_eq!(to_latex(&parse("asin(x)").unwrap()), "\\arcsin\\left(x\\right)");
+        assert_eq!(to_latex(&parse("sinh(x)").unwrap()), "\\sinh\\left(x\\right)");
+    }
+}
+
+#[cfg(test)]
+mod multi_argument_tests {
+    use super::*;
+    use proton_lite::differentiate::try_differentiate_with;
+    use proton_lite::eval::EvalContext;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::parse;
+    use proton_lite::registry::{builtins, Arity, Function, FunctionRegistry};
+    use proton_lite::simplify::simplify;
+
+    #[test]
+    fn test_relu() {
+        let d = simplify(&differentiate(&parse("max(x, 0)").unwrap(), "x"));
+        assert_eq!(at(&d, 2.0, 0.0), 1.0);
+        assert_eq!(at(&d, -2.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_two_argument_builtins() {
+        // Compare each partial with a central difference
+        let h = 1e-6;
+        for source in ["atan2(y, x)", "hypot(x, y)", "pow(x, y)", "pow(x ^ 2, sin(y))", "max(x * y, y)"] {
+            let expr = parse(source).unwrap();
+            let (x, y) = (1.3, 0.4);
+            let dx = differentiate(&expr, "x");
+            let dy = differentiate(&expr, "y");
+            let numeric_dx = (at(&expr, x + h, y) - at(&expr, x - h, y)) / (2.0 * h);
+            let numeric_dy = (at(&expr, x, y + h) - at(&expr, x, y - h)) / (2.0 * h);
+            assert!((at(&dx, x, y) - numeric_dx).abs() < 1e-6, "d/dx {}", source);
+            assert!((at(&dy, x, y) - numeric_dy).abs() < 1e-6, "d/dy {}", source);
+        }
+
+        // Arguments that do not depend on the variable drop out of the chain rule
+        assert_eq!(to_string(&differentiate(&parse("hypot(x, 3)").unwrap(), "x")), "(x / hypot(x, 3))");
+    }
+
+    #[test]
+    fn test_registered_partials() {
+        let mut registry = FunctionRegistry::default();
+        registry.register(
+            // lerp(a, b, t) = a + (b - a) * t
+            Function::new("lerp", Arity::Exact(3), |args| args[0] + (args[1] - args[0]) * args[2]).with_partials(
+                |args| {
+                    let t = args[2].clone();
+                    vec![
+                        Expr::Sub(Box::new(Expr::Number(1.0)), Box::new(t.clone())),
+                        t,
+                        Expr::Sub(Box::new(args[1].clone()), Box::new(args[0].clone())),
+                    ]
+                },
+            ),
+        );
+
+        let expr = parse("lerp(x, x ^ 2, y)").unwrap();
+        let d = try_differentiate_with(&expr, "x", &registry).unwrap();
+        let vars = HashMap::from([("x".to_string(), 3.0), ("y".to_string(), 0.25)]);
+        // (1 - t) * 1 + t * 2x = 0.75 + 1.5
+        assert_eq!(EvalContext::new(&vars).with_functions(&registry).evaluate(&d), Ok(2.25));
+
+        let args = [parse("a").unwrap(), parse("b").unwrap(), parse("t").unwrap()];
+        let lerp = registry.get("lerp").unwrap();
+        assert_eq!(lerp.partial(&args, 2).map(|p| to_string(&p)), Some("(b - a)".to_string()));
+        assert_eq!(
+            builtins().get("hypot").unwrap().partial(&args[..2], 1).map(|p| to_string(&p)),
+            Some("(b / hypot(a, b))".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod higher_order_tests {
+    use super::*;
+    use proton_lite::differentiate::{differentiate_n, partial, try_partial};
+    use proton_lite::error::ProtonError;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::parse;
+
+    #[test]
+    fn test_differentiate_n() {
+        let expr = parse("x ^ 4").unwrap();
+        assert_eq!(to_string(&differentiate_n(&expr, "x", 2)), "(12 * (x ^ 2))");
+        assert_eq!(differentiate_n(&expr, "x", 4), Expr::Number(24.0));
+        assert_eq!(differentiate_n(&expr, "x", 7), Expr::Number(0.0));
+        assert_eq!(differentiate_n(&expr, "x", 0), expr);
+
+        // sin cycles back to itself after four steps
+        let x = HashMap::from([("x".to_string(), 0.3)]);
+        let d4 = differentiate_n(&parse("sin(x)").unwrap(), "x", 4);
+        assert!((evaluate(&d4, &x) - 0.3_f64.sin()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_mixed_partials() {
+        let expr = parse("x ^ 3 * y ^ 2 + sin(x * y)").unwrap();
+        let dxxy = partial(&expr, &[("x", 2), ("y", 1)]);
+        let dyxx = partial(&expr, &[("y", 1), ("x", 2)]);
+
+        // 12xy - 2y sin(xy) - x y^2 cos(xy), in either order
+        let (x, y): (f64, f64) = (0.7, -1.1);
+        let vars = HashMap::from([("x".to_string(), x), ("y".to_string(), y)]);
+        let expected = 12.0 * x * y - 2.0 * y * (x * y).sin() - x * y * y * (x * y).cos();
+        assert!((evaluate(&dxxy, &vars) - expected).abs() < 1e-9);
+        assert!((evaluate(&dyxx, &vars) - expected).abs() < 1e-9);
+
+        assert_eq!(
+            try_partial(&parse("gamma(x)").unwrap(), &[("x", 1)]),
+            Err(ProtonError::UnknownFunction("gamma".to_string()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod gradient_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::expr::to_string;
+    use proton_lite::gradient::{evaluate_matrix, gradient, hessian, jacobian, try_evaluate_matrix};
+    use proton_lite::parse::parse;
+
+    fn strings(matrix: &[Vec<Expr>]) -> Vec<Vec<String>> {
+        matrix.iter().map(|row| row.iter().map(to_string).collect()).collect()
+    }
+
+    #[test]
+    fn test_gradient_and_jacobian() {
+        let f = parse("x ^ 2 * y + 3 * y").unwrap();
+        let g: Vec<String> = gradient(&f, &["x", "y"]).iter().map(to_string).collect();
+        assert_eq!(g, vec!["((2 * x) * y)", "((x ^ 2) + 3)"]);
+
+        let exprs = [parse("x * y").unwrap(), parse("sin(x) + y").unwrap()];
+        let j = jacobian(&exprs, &["x", "y"]);
+        assert_eq!(strings(&j), vec![vec!["y", "x"], vec!["cos(x)", "1"]]);
+
+        let point = HashMap::from([("x".to_string(), 0.0), ("y".to_string(), 2.0)]);
+        assert_eq!(evaluate_matrix(&j, &point), vec![vec![2.0, 0.0], vec![1.0, 1.0]]);
+    }
+
+    #[test]
+    fn test_hessian() {
+        // Rosenbrock, whose Hessian at its minimum (1, 1) is [[802, -400], [-400, 200]]
+        let f = parse("(1 - x) ^ 2 + 100 * (y - x ^ 2) ^ 2").unwrap();
+        let h = hessian(&f, &["x", "y"]);
+        assert_eq!(h[0][1], h[1][0]);
+
+        let point = HashMap::from([("x".to_string(), 1.0), ("y".to_string(), 1.0)]);
+        assert_eq!(try_evaluate_matrix(&h, &point), Ok(vec![vec![802.0, -400.0], vec![-400.0, 200.0]]));
+
+        let unbound = HashMap::from([("x".to_string(), 1.0)]);
+        assert_eq!(
+            try_evaluate_matrix(&h, &unbound),
+            Err(ProtonError::UnboundVariables(vec!["y".to_string()]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod autodiff_tests {
+    use super::*;
+    use proton_lite::autodiff::{gradient_at, try_gradient_at, try_gradient_at_with};
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::EvalContext;
+    use proton_lite::parse::parse;
+    use proton_lite::registry::{Function, FunctionRegistry};
+
+    #[test]
+    fn test_matches_symbolic_derivatives() {
+        let vars = HashMap::from([("x".to_string(), 0.8), ("y".to_string(), 1.7), ("z".to_string(), -0.4)]);
+        let cases = [
+            "x * y + sin(x * z)",
+            "x ^ 3 * y / (1 + z ^ 2)",
+            "max(x, y) * exp(z) - log(2, y)",
+            "hypot(x, y) + atan2(z, x) + pow(y, x)",
+            "x ^ 2 + x ^ 0 + y ^ 3",
+        ];
+        for source in cases {
+            let expr = parse(source).unwrap();
+            let result = gradient_at(&expr, &vars);
+            assert_eq!(result.value, evaluate(&expr, &vars));
+            for var in ["x", "y", "z"] {
+                let symbolic = evaluate(&differentiate(&expr, var), &vars);
+                let reverse = result.partials.get(var).copied().unwrap_or(0.0);
+                assert!((symbolic - reverse).abs() < 1e-12, "d/d{} {}: {} vs {}", var, source, symbolic, reverse);
+            }
+        }
+
+        // A variable exponent: ∂/∂x = y * x^(y - 1), ∂/∂y = x^y * ln(x)
+        let (x, y): (f64, f64) = (0.8, 1.7);
+        let result = gradient_at(&parse("x ^ y").unwrap(), &vars);
+        assert!((result.partials["x"] - y * x.powf(y - 1.0)).abs() < 1e-12);
+        assert!((result.partials["y"] - x.powf(y) * x.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_many_variables() {
+        // f = sum of i * x_i^2, so ∂f/∂x_i = 2 * i * x_i
+        let names: Vec<String> = (0..300).map(|i| format!("x{}", i)).collect();
+        let expr = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| parse(&format!("{} * {} ^ 2", i, name)).unwrap())
+            .reduce(|sum, term| Expr::Add(Box::new(sum), Box::new(term)))
+            .unwrap();
+        let vars: HashMap<String, f64> = names.iter().map(|name| (name.clone(), 0.5)).collect();
+
+        let result = try_gradient_at(&expr, &vars).unwrap();
+        assert_eq!(result.partials.len(), 300);
+        assert_eq!(result.partials["x10"], 10.0);
+        assert_eq!(result.partials["x299"], 299.0);
+    }
+
+    #[test]
+    fn test_shares_function_table() {
+        let mut registry = FunctionRegistry::default();
+        registry.register(Function::unary("cube", |x| x * x * x));
+        registry.define("f", &["a", "b"], parse("a * b ^ 2").unwrap()).unwrap();
+        let vars = HashMap::from([("x".to_string(), 2.0)]);
+        let context = EvalContext::new(&vars).with_functions(&registry);
+
+        let result = try_gradient_at_with(&parse("f(x, x + 1)").unwrap(), &context).unwrap();
+        assert_eq!(result.value, 18.0);
+        assert_eq!(result.partials["x"], 9.0 + 12.0);
+
+        // A function the table can evaluate but not differentiate
+        assert!(matches!(
+            try_gradient_at_with(&parse("cube(x)").unwrap(), &context),
+            Err(ProtonError::UnsupportedFunction { operation: "differentiate", .. })
+        ));
+        // ...unless its argument is constant
+        assert_eq!(try_gradient_at_with(&parse("cube(2) * x").unwrap(), &context).unwrap().partials["x"], 8.0);
+        assert_eq!(
+            try_gradient_at(&parse("x + y").unwrap(), &vars),
+            Err(ProtonError::UnboundVariables(vec!["y".to_string()]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod dual_tests {
+    use super::*;
+    use proton_lite::differentiate::{differentiate_n, partial};
+    use proton_lite::dual::{evaluate_dual, evaluate_hyper_dual, try_evaluate_dual, Dual};
+    use proton_lite::error::ProtonError;
+    use proton_lite::parse::parse;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-10 * (1.0 + b.abs())
+    }
+
+    #[test]
+    fn test_dual_matches_differentiate() {
+        let vars = HashMap::from([("x".to_string(), 0.9), ("y".to_string(), 1.4)]);
+        for source in ["x ^ 3 * y / (1 + x)", "sin(x * y) + exp(-x)", "sqrt(hypot(x, y)) * atan(x)", "max(x, y) ^ 2"] {
+            let expr = parse(source).unwrap();
+            let dual = evaluate_dual(&expr, "x", &vars);
+            assert!(close(dual.value, evaluate(&expr, &vars)), "{}", source);
+            assert!(close(dual.derivative, evaluate(&differentiate(&expr, "x"), &vars)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_hyper_dual_second_derivatives() {
+        let vars = HashMap::from([("x".to_string(), 0.7), ("y".to_string(), -0.3)]);
+        for source in ["x ^ 4 + x * y ^ 2", "ln(x) / cos(y)", "exp(x * y) * sin(x)", "atan2(y, x) + x ^ 0"] {
+            let expr = parse(source).unwrap();
+            let xx = evaluate_hyper_dual(&expr, "x", "x", &vars);
+            assert!(close(xx.e1, evaluate(&differentiate(&expr, "x"), &vars)), "{}", source);
+            assert!(close(xx.e1e2, evaluate(&differentiate_n(&expr, "x", 2), &vars)), "{}", source);
+
+            let xy = evaluate_hyper_dual(&expr, "x", "y", &vars);
+            assert!(close(xy.e2, evaluate(&differentiate(&expr, "y"), &vars)), "{}", source);
+            assert!(close(xy.e1e2, evaluate(&partial(&expr, &[("x", 1), ("y", 1)]), &vars)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_newton_iteration() {
+        // Solve x^3 - 2x - 5 = 0 with derivatives from dual numbers
+        let f = parse("x ^ 3 - 2 * x - 5").unwrap();
+        let mut x = 2.0;
+        for _ in 0..20 {
+            let Dual { value, derivative } = evaluate_dual(&f, "x", &HashMap::from([("x".to_string(), x)]));
+            x -= value / derivative;
+        }
+        assert!((x - 2.0945514815423265_f64).abs() < 1e-12);
+
+        assert_eq!(
+            try_evaluate_dual(&parse("x * y").unwrap(), "x", &HashMap::from([("x".to_string(), 1.0)])),
+            Err(ProtonError::UnboundVariables(vec!["y".to_string()]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod power_rule_tests {
+    use super::*;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::parse;
+    use proton_lite::simplify::simplify;
+
+    #[test]
+    fn test_x_to_the_x() {
+        let d = differentiate(&parse("x ^ x").unwrap(), "x");
+        for x in [0.5_f64, 1.0, 2.5] {
+            let expected = x.powf(x) * (x.ln() + 1.0);
+            let value = evaluate(&d, &HashMap::from([("x".to_string(), x)]));
+            assert!((value - expected).abs() < 1e-12, "at {}: {} vs {}", x, value, expected);
+        }
+    }
+
+    #[test]
+    fn test_fast_paths() {
+        let d = |source: &str| to_string(&simplify(&differentiate(&parse(source).unwrap(), "x")));
+        assert_eq!(d("x ^ 3"), "(3 * (x ^ 2))");
+        assert_eq!(d("x ^ a"), "(a * (x ^ (a - 1)))");
+        assert_eq!(d("2 ^ x"), format!("((2 ^ x) * {})", 2.0_f64.ln()));
+        assert_eq!(d("a ^ x"), "((a ^ x) * ln(a))");
+        assert_eq!(d("a ^ b"), "0");
+    }
+
+    // Every combination of these bases and exponents, checked against a central
+    // difference at several points
+    #[test]
+    fn test_power_rule_matches_numerical_derivative() {
+        let bases = ["x", "2", "a", "x + 1", "sin(x) + 2", "a * x ^ 2"];
+        let exponents = ["3", "-0.5", "x", "a", "2 * x", "cos(x)", "x / a"];
+        let h = 1e-5;
+        for base in bases {
+            for exponent in exponents {
+                let source = format!("({}) ^ ({})", base, exponent);
+                let expr = parse(&source).unwrap();
+                let derivative = differentiate(&expr, "x");
+                for x in [0.4, 1.1, 1.9] {
+                    let at = |x: f64| evaluate(&expr, &HashMap::from([("x".to_string(), x), ("a".to_string(), 1.7)]));
+                    let numeric = (at(x + h) - at(x - h)) / (2.0 * h);
+                    let vars = HashMap::from([("x".to_string(), x), ("a".to_string(), 1.7)]);
+                    let symbolic = evaluate(&derivative, &vars);
+                    assert!(
+                        (symbolic - numeric).abs() < 1e-6 * (1.0 + numeric.abs()),
+                        "d/dx {} at {}: {} vs {}",
+                        source, x, symbolic, numeric
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod finite_difference_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::eval::EvalContext;
+    use proton_lite::finite_difference::{derivative, derivative_with, second_derivative, Scheme};
+    use proton_lite::parse::parse;
+    use proton_lite::registry::{Function, FunctionRegistry};
+
+    #[test]
+    fn test_schemes_and_error_estimates() {
+        let expr = parse("exp(x) * sin(3 * x)").unwrap();
+        let x: f64 = 0.6;
+        let exact = x.exp() * (3.0 * x).sin() + 3.0 * x.exp() * (3.0 * x).cos();
+        let vars = HashMap::from([("x".to_string(), x)]);
+
+        let mut previous_error = f64::INFINITY;
+        for (scheme, tolerance) in [(Scheme::Forward, 1e-6), (Scheme::Central, 1e-9), (Scheme::Richardson, 1e-12)] {
+            let estimate = derivative(&expr, "x", &vars, scheme).unwrap();
+            let actual = (estimate.value - exact).abs();
+            assert!(actual < tolerance, "{:?}: off by {}", scheme, actual);
+            // The estimate is of the right size, and each scheme is better than the last
+            assert!(actual <= 10.0 * estimate.error + 1e-14, "{:?}: {} vs {}", scheme, actual, estimate.error);
+            assert!(actual < previous_error);
+            previous_error = actual;
+        }
+    }
+
+    #[test]
+    fn test_partial_and_second_derivatives() {
+        let expr = parse("x ^ 2 * y + y ^ 3").unwrap();
+        let vars = HashMap::from([("x".to_string(), 1.5), ("y".to_string(), -2.0)]);
+
+        let dy = derivative(&expr, "y", &vars, Scheme::Richardson).unwrap();
+        assert!((dy.value - (2.25 + 12.0)).abs() < 1e-10);
+
+        let dxy = second_derivative(&expr, "x", "y", &vars).unwrap();
+        assert!((dxy.value - 3.0).abs() < 1e-5);
+        let dyy = second_derivative(&expr, "y", "y", &vars).unwrap();
+        assert!((dyy.value - -12.0).abs() < 1e-5);
+        assert_eq!(dyy.evaluations, 6);
+    }
+
+    #[test]
+    fn test_functions_without_derivative_rules() {
+        let mut registry = FunctionRegistry::default();
+        registry.register(Function::unary("cube", |x| x * x * x));
+        let vars = HashMap::from([("x".to_string(), 2.0)]);
+        let context = EvalContext::new(&vars).with_functions(&registry);
+
+        let estimate = derivative_with(&parse("cube(x)").unwrap(), "x", &context, Scheme::Richardson).unwrap();
+        assert!((estimate.value - 12.0).abs() < 1e-10);
+
+        assert_eq!(
+            derivative(&parse("x * y").unwrap(), "y", &vars, Scheme::Central),
+            Err(ProtonError::UnboundVariables(vec!["y".to_string()]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod implicit_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::implicit::{implicit_derivative, implicit_derivative_n, try_implicit_derivative, try_implicit_derivative_n};
+    use proton_lite::parse::parse;
+
+    #[test]
+    fn test_circle() {
+        // x^2 + y^2 = 25: y' = -x / y, y'' = -25 / y^3
+        let circle = parse("x ^ 2 + y ^ 2 - 25").unwrap();
+        let (x, y) = (3.0, 4.0);
+        assert_eq!(at(&implicit_derivative(&circle, "x", "y"), x, y), -0.75);
+        assert!((at(&implicit_derivative_n(&circle, "x", "y", 2), x, y) - -25.0 / 64.0).abs() < 1e-12);
+        // y''' = -75 x / y^5
+        assert!((at(&implicit_derivative_n(&circle, "x", "y", 3), x, y) - -225.0 / 1024.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_matches_explicit_curve() {
+        // y = exp(x) is the curve ln(y) - x = 0
+        let curve = parse("ln(y) - x").unwrap();
+        for n in 1..=3 {
+            let d = implicit_derivative_n(&curve, "x", "y", n);
+            assert!((at(&d, 0.5, 0.5_f64.exp()) - 0.5_f64.exp()).abs() < 1e-12, "order {}", n);
+        }
+
+        assert_eq!(
+            try_implicit_derivative_n(&parse("x ^ 2 - 1").unwrap(), "x", "y", 1),
+            Err(ProtonError::DivisionByZero)
+        );
+        assert_eq!(try_implicit_derivative(&parse("x ^ 2 - 1").unwrap(), "x", "y"), Err(ProtonError::DivisionByZero));
+    }
+}
+
+#[cfg(test)]
+mod vector_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::expr::to_string;
+    use proton_lite::parse::parse;
+    use proton_lite::vector::{
+        curl, divergence, directional_derivative, gradient, laplacian, try_curl, CoordinateSystem,
+    };
+
+    fn field(sources: &[&str]) -> Vec<Expr> {
+        sources.iter().map(|source| parse(source).unwrap()).collect()
+    }
+
+    fn at(expr: &Expr, names: [&str; 3], values: [f64; 3]) -> f64 {
+        let vars = names.iter().map(|name| name.to_string()).zip(values).collect();
+        evaluate(expr, &vars)
+    }
+
+    #[test]
+    fn test_cartesian() {
+        let xyz = ["x", "y", "z"];
+        let f = field(&["x * y", "y * z", "z * x"]);
+        assert_eq!(to_string(&divergence(&f, &xyz, CoordinateSystem::Cartesian)), "((y + z) + x)");
+        let c: Vec<String> = curl(&f, &xyz, CoordinateSystem::Cartesian).iter().map(to_string).collect();
+        assert_eq!(c, vec!["(0 - y)", "(0 - z)", "(0 - x)"]);
+
+        let phi = parse("x ^ 2 * y + z ^ 3").unwrap();
+        let l = laplacian(&phi, &xyz, CoordinateSystem::Cartesian);
+        assert_eq!(at(&l, xyz, [1.0, 2.0, 3.0]), 4.0 + 18.0);
+
+        // Two dimensions work for everything but the curl
+        let d = directional_derivative(&parse("x ^ 2 + y ^ 2").unwrap(), &field(&["1", "1"]), &["x", "y"], CoordinateSystem::Cartesian);
+        let vars = HashMap::from([("x".to_string(), 1.0), ("y".to_string(), 2.0)]);
+        assert!((evaluate(&d, &vars) - 6.0 / 2.0_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(
+            try_curl(&field(&["x", "y"]), &["x", "y"], CoordinateSystem::Cartesian),
+            Err(ProtonError::DimensionMismatch { expected: 3, found: 2 })
+        );
+    }
+
+    #[test]
+    fn test_cylindrical() {
+        let coords = ["r", "t", "z"];
+        let system = CoordinateSystem::Cylindrical;
+        let point = [1.5, 0.4, -0.7];
+        assert_eq!(at(&divergence(&field(&["r", "0", "0"]), &coords, system), coords, point), 2.0);
+        assert_eq!(at(&laplacian(&parse("r ^ 2").unwrap(), &coords, system), coords, point), 4.0);
+
+        // A rigid rotation has constant curl 2 along z
+        let c = curl(&field(&["0", "r", "0"]), &coords, system);
+        let values: Vec<f64> = c.iter().map(|component| at(component, coords, point)).collect();
+        assert_eq!(values, vec![0.0, 0.0, 2.0]);
+
+        // The angular component of the gradient is scaled by 1 / r
+        let g = gradient(&parse("r * t").unwrap(), &coords, system);
+        assert_eq!(at(&g[1], coords, point), 1.0);
+    }
+
+    #[test]
+    fn test_spherical() {
+        let coords = ["r", "theta", "phi"];
+        let system = CoordinateSystem::Spherical;
+        let point = [2.0, 0.9, 2.5];
+        assert!((at(&divergence(&field(&["r", "0", "0"]), &coords, system), coords, point) - 3.0).abs() < 1e-12);
+        assert!((at(&laplacian(&parse("r ^ 2").unwrap(), &coords, system), coords, point) - 6.0).abs() < 1e-12);
+        // 1 / r is harmonic away from the origin
+        assert!(at(&laplacian(&parse("1 / r").unwrap(), &coords, system), coords, point).abs() < 1e-12);
+
+        // The curl of a gradient vanishes
+        let f = parse("r ^ 2 * sin(theta) * cos(phi)").unwrap();
+        for component in curl(&gradient(&f, &coords, system), &coords, system) {
+            assert!(at(&component, coords, point).abs() < 1e-12);
+        }
+    }
+}
+
+#[cfg(test)]
+mod by_parts_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::integrate::try_integrate;
+    use proton_lite::parse::parse;
+
+    const POINTS: [f64; 3] = [0.3, 1.2, 2.7];
+
+    #[test]
+    fn test_liate_products() {
+        for source in [
+            "x * sin(x)",
+            "x ^ 2 * exp(x)",
+            "x * ln(x)",
+            "cos(x) * x ^ 3",
+            "x ^ 4 * exp(x)",
+            "a * x * cos(x)",
+            "ln(x) * x ^ 2",
+            "x * 2 ^ x",
+        ] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_cyclic_products() {
+        for source in ["exp(x) * sin(x)", "cos(x) * exp(x)", "3 * exp(x) * cos(x)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_monomials_and_constants() {
+        for source in ["x * x", "(x ^ 3) / (2 * x)", "1 / x", "a", "a * x ^ 2"] {
+            assert_antiderivative(source, &POINTS);
+        }
+        // Products with no LIATE factor to differentiate away still fail cleanly
+        assert!(matches!(try_integrate(&parse("sin(x) * cos(x ^ 2)").unwrap(), "x"), Err(ProtonError::NoClosedForm(_))));
+    }
+
+    #[test]
+    fn test_powers_of_logarithms() {
+        for source in ["ln(x) ^ 2", "ln(x) ^ 3", "x * ln(x) ^ 2"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_errors_name_the_integrand() {
+        assert_eq!(
+            try_integrate(&parse("x * exp(x) * sin(x)").unwrap(), "x"),
+            Err(ProtonError::NoClosedForm("((x * exp(x)) * sin(x))".to_string()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod substitution_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::expr::to_string;
+    use proton_lite::integrate::try_integrate;
+    use proton_lite::parse::parse;
+
+    const POINTS: [f64; 3] = [0.3, 0.8, 1.4];
+
+    #[test]
+    fn test_linear_inner_arguments() {
+        let integral = try_integrate(&parse("sin(2 * x + 1)").unwrap(), "x").unwrap();
+        assert_eq!(to_string(&integral), "(-0.5 * cos(((2 * x) + 1)))");
+        for source in ["sin(2 * x + 1)", "exp(3 * x)", "cos(a * x)", "(2 * x + 1) ^ 3", "1 / (3 * x + 2)", "2 ^ (x / 4)", "sqrt(2 - x)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_chain_rule_reversal() {
+        for source in [
+            "2 * x * cos(x ^ 2)",
+            "x * exp(x ^ 2)",
+            "sin(x) * cos(x)",
+            "cos(x) / sin(x)",
+            "x / (x ^ 2 + 1)",
+            "exp(x) * sin(exp(x))",
+            "ln(x) / x",
+            "atan(x)",
+            "x * sin(a * x)",
+        ] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_logarithms_take_the_absolute_value() {
+        let integral = try_integrate(&parse("1 / (2 * x + 1)").unwrap(), "x").unwrap();
+        assert_eq!(to_string(&integral), "(0.5 * ln(abs(((2 * x) + 1))))");
+        for source in ["1 / (2 * x + 1)", "cos(x) / sin(x)", "x / (x ^ 2 - 4)"] {
+            assert_antiderivative(source, &[-2.7, -1.2, 0.3]);
+        }
+    }
+
+    #[test]
+    fn test_no_silently_wrong_results() {
+        // Neither has an elementary antiderivative
+        assert!(matches!(try_integrate(&parse("sin(x ^ 2)").unwrap(), "x"), Err(ProtonError::NoClosedForm(_))));
+        assert!(matches!(try_integrate(&parse("exp(x) / x").unwrap(), "x"), Err(ProtonError::NoClosedForm(_))));
+    }
+}
+
+#[cfg(test)]
+mod partial_fractions_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::expr::to_string;
+    use proton_lite::integrate::try_integrate;
+    use proton_lite::parse::parse;
+
+    const POINTS: [f64; 3] = [-2.5, 0.3, 2.7];
+
+    #[test]
+    fn test_distinct_linear_factors() {
+        let integral = try_integrate(&parse("1 / (x ^ 2 - 1)").unwrap(), "x").unwrap();
+        assert_eq!(to_string(&integral), "((-0.5 * ln(abs((x + 1)))) + (0.5 * ln(abs((x - 1)))))");
+        for source in ["1 / (x ^ 2 - 1)", "(2 * x + 3) / (x ^ 3 - x)", "1 / (2 * x ^ 2 - 5 * x - 3)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_irreducible_quadratics() {
+        let integral = try_integrate(&parse("(3 * x + 2) / (x ^ 2 + 2 * x + 5)").unwrap(), "x").unwrap();
+        assert_eq!(to_string(&integral), "((1.5 * ln((((x ^ 2) + (2 * x)) + 5))) + (-0.5 * atan(((x + 1) / 2))))");
+        for source in ["(3 * x + 2) / (x ^ 2 + 2 * x + 5)", "1 / (2 * x ^ 2 + 3)", "1 / (x ^ 3 - 1)", "x ^ 2 / (x ^ 4 + 1)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_repeated_factors() {
+        for source in ["1 / (x * (x + 1) ^ 2)", "x ^ 2 / (x - 1) ^ 3", "1 / (x ^ 2 + 1) ^ 2", "(x ^ 4 + 2) / ((x - 3) * (x ^ 2 + x + 1) ^ 2)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_improper_fractions() {
+        for source in ["(x ^ 3 + 1) / (x ^ 2 - 4)", "(x ^ 2 + 1) / (x + 5)", "(x + 1) * (x + 2)"] {
+            assert_antiderivative(source, &POINTS);
+        }
+    }
+
+    #[test]
+    fn test_near_equal_roots() {
+        // Roots this close must not be merged into a double root
+        for source in ["1 / ((x - 1) * (x - 1.000001))", "1 / (x ^ 2 - 2 * x + 1.000000001)", "1 / ((x - 1) ^ 2 * (x - 1.0001))"] {
+            let expr = parse(source).unwrap();
+            match try_integrate(&expr, "x") {
+                Ok(integral) => {
+                    let derivative = differentiate(&integral, "x");
+                    for x in [0.999, 1.0000005, 1.00005, 1.002] {
+                        let (expected, found) = (at(&expr, x, 0.0), at(&derivative, x, 0.0));
+                        assert!((expected - found).abs() < 1e-6 * expected.abs(), "{} at {}: {} vs {}", source, x, found, expected);
+                    }
+                }
+                Err(err) => assert_eq!(err, ProtonError::NoClosedForm(to_string(&expr))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod definite_tests {
+    use super::*;
+    use proton_lite::definite::{try_integrate_definite, DefiniteIntegral};
+    use proton_lite::error::ProtonError;
+    use proton_lite::parse::parse;
+
+    fn definite(source: &str, lower: &str, upper: &str) -> Result<DefiniteIntegral, ProtonError> {
+        try_integrate_definite(&parse(source).unwrap(), "x", &parse(lower).unwrap(), &parse(upper).unwrap())
+    }
+
+    #[test]
+    fn test_numeric_bounds() {
+        match definite("x ^ 2 + 1", "0", "3").unwrap() {
+            DefiniteIntegral::Symbolic(Expr::Number(value)) => assert!((value - 12.0).abs() < 1e-12),
+            other => panic!("expected an exact value, found {:?}", other),
+        }
+        match definite("1 / (x ^ 2 + 1)", "0", "1").unwrap() {
+            DefiniteIntegral::Symbolic(Expr::Number(value)) => assert!((value - std::f64::consts::FRAC_PI_4).abs() < 1e-12),
+            other => panic!("expected an exact value, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_symbolic_bounds() {
+        let DefiniteIntegral::Symbolic(value) = definite("2 * x * cos(x ^ 2)", "0", "sqrt(t)").unwrap() else {
+            panic!("expected a symbolic result");
+        };
+        let vars = HashMap::from([("t".to_string(), 1.2)]);
+        assert!((evaluate(&value, &vars) - 1.2_f64.sin()).abs() < 1e-12);
+
+        let DefiniteIntegral::Symbolic(value) = definite("exp(x)", "a", "2 * a").unwrap() else {
+            panic!("expected a symbolic result");
+        };
+        let vars = HashMap::from([("a".to_string(), 0.5)]);
+        assert!((evaluate(&value, &vars) - (1.0_f64.exp() - 0.5_f64.exp())).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_numeric_fallback() {
+        match definite("sin(x ^ 2)", "0", "1").unwrap() {
+            DefiniteIntegral::Numeric(estimate) => {
+                assert!((estimate.value - 0.310_268_301_723_381_1).abs() < 1e-9);
+                assert!(estimate.error < 1e-8 && estimate.evaluations > 0);
+            }
+            other => panic!("expected a numeric result, found {:?}", other),
+        }
+        match definite("exp(-x ^ 2)", "0", "1").unwrap() {
+            DefiniteIntegral::Numeric(estimate) => assert!((estimate.value - 0.746_824_132_812_427).abs() < 1e-9),
+            other => panic!("expected a numeric result, found {:?}", other),
+        }
+        // Singular at both bounds, where Gauss–Kronrod gives way to tanh-sinh;
+        // 1 - x^2 cancels next to them, which costs some digits
+        match definite("1 / sqrt(1 - x ^ 2)", "-1", "1").unwrap() {
+            DefiniteIntegral::Numeric(estimate) => assert!((estimate.value - std::f64::consts::PI).abs() < 1e-7),
+            other => panic!("expected a numeric result, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slow_quadrature_keeps_the_closed_form() {
+        assert_eq!(definite("cos(x)", "0", "1e6").unwrap(), DefiniteIntegral::Symbolic(Expr::Number(1e6_f64.sin())));
+    }
+
+    #[test]
+    fn test_poles_inside_the_interval() {
+        assert!(matches!(definite("1 / x ^ 2", "-1", "1"), Err(ProtonError::NonFiniteValue { .. })));
+        assert!(matches!(definite("1 / (x ^ 2 - 1)", "0", "2"), Err(ProtonError::NonFiniteValue { .. })));
+        assert!(matches!(definite("tan(x)", "0", "3"), Err(ProtonError::Diverged { .. })));
+        assert!(matches!(definite("1 / (x - 0.1)", "-1", "1"), Err(ProtonError::Diverged { .. })));
+        let infinity = Expr::Number(f64::INFINITY);
+        assert!(matches!(
+            try_integrate_definite(&parse("x").unwrap(), "x", &Expr::Number(0.0), &infinity),
+            Err(ProtonError::Diverged { .. })
+        ));
+        // Integrable singularities at a bound are fine
+        match definite("x ^ -0.5", "0", "4").unwrap() {
+            DefiniteIntegral::Symbolic(Expr::Number(value)) => assert!((value - 4.0).abs() < 1e-12),
+            other => panic!("expected an exact value, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_fallback_with_symbolic_bounds() {
+        assert!(matches!(definite("sin(x ^ 2)", "0", "t"), Err(ProtonError::NoClosedForm(_))));
+    }
+}
+
+#[cfg(test)]
+mod quadrature_tests {
+    use super::*;
+    use proton_lite::error::ProtonError;
+    use proton_lite::parse::parse;
+    use proton_lite::quadrature::{quadrature, Method};
+    use std::f64::consts::PI;
+
+    const METHODS: [Method; 4] = [Method::AdaptiveSimpson, Method::GaussKronrod15, Method::GaussKronrod21, Method::TanhSinh];
+
+    fn assert_integral(source: &str, lower: f64, upper: f64, expected: f64, method: Method) {
+        let estimate = quadrature(&parse(source).unwrap(), "x", lower, upper, &HashMap::new(), method).unwrap();
+        assert!((estimate.value - expected).abs() < 1e-8, "{} with {:?}: {:?}, expected {}", source, method, estimate, expected);
+        assert!(estimate.error < 1e-6 && estimate.evaluations > 0, "{} with {:?}: {:?}", source, method, estimate);
+    }
+
+    #[test]
+    fn test_smooth_integrands() {
+        for method in METHODS {
+            assert_integral("sin(x)", 0.0, PI, 2.0, method);
+            assert_integral("exp(x)", 0.0, 1.0, 1.0_f64.exp() - 1.0, method);
+            assert_integral("1 / (1 + x ^ 2)", -1.0, 1.0, PI / 2.0, method);
+        }
+    }
+
+    #[test]
+    fn test_kronrod_rules_are_exact_on_polynomials() {
+        // Both the Kronrod rule and its Gauss rule are exact here, so one
+        // application with no error is enough
+        for (method, degree, points) in [(Method::GaussKronrod15, 12, 15), (Method::GaussKronrod21, 18, 21)] {
+            let source = format!("x ^ {} + 3 * x ^ 5 - 2", degree);
+            let estimate = quadrature(&parse(&source).unwrap(), "x", -1.0, 1.0, &HashMap::new(), method).unwrap();
+            assert!((estimate.value - (2.0 / (degree as f64 + 1.0) - 4.0)).abs() < 1e-14);
+            assert!(estimate.error < 1e-14);
+            assert_eq!(estimate.evaluations, points);
+        }
+    }
+
+    #[test]
+    fn test_endpoint_singularities() {
+        assert_integral("x ^ -0.8", 0.0, 1.0, 5.0, Method::GaussKronrod21);
+        assert_integral("1 / sqrt(x)", 0.0, 1.0, 2.0, Method::TanhSinh);
+        assert_integral("ln(x)", 0.0, 1.0, -1.0, Method::TanhSinh);
+        // Near 1, 1 - x ^ 2 is limited by how finely x itself can be represented
+        let estimate = quadrature(&parse("1 / sqrt(1 - x ^ 2)").unwrap(), "x", -1.0, 1.0, &HashMap::new(), Method::TanhSinh).unwrap();
+        assert!((estimate.value - PI).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_infinite_intervals() {
+        for method in [Method::GaussKronrod15, Method::GaussKronrod21, Method::TanhSinh] {
+            assert_integral("exp(-x ^ 2)", f64::NEG_INFINITY, f64::INFINITY, PI.sqrt(), method);
+            assert_integral("1 / (1 + x ^ 2)", 0.0, f64::INFINITY, PI / 2.0, method);
+            assert_integral("exp(x)", f64::NEG_INFINITY, 0.0, 1.0, method);
+        }
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let failure = |source: &str, lower: f64, upper: f64, method: Method| {
+            quadrature(&parse(source).unwrap(), "x", lower, upper, &HashMap::new(), method).unwrap_err()
+        };
+        // Simpson's rule evaluates the endpoints
+        assert_eq!(failure("1 / sqrt(x)", 0.0, 1.0, Method::AdaptiveSimpson), ProtonError::NonFiniteValue { var: "x".to_string(), at: 0.0 });
+        assert!(matches!(failure("1 / x ^ 2", -1.0, 1.0, Method::GaussKronrod21), ProtonError::NonFiniteValue { .. }));
+        // Divergent integrals are told apart from slow ones
+        assert!(matches!(failure("1 / x", 0.0, 1.0, Method::GaussKronrod15), ProtonError::Diverged { .. }));
+        assert!(matches!(failure("1 / x", 1.0, f64::INFINITY, Method::GaussKronrod15), ProtonError::Diverged { .. }));
+        assert!(matches!(failure("tan(x)", 0.0, 3.0, Method::GaussKronrod21), ProtonError::Diverged { .. }));
+        assert!(matches!(failure("x", 0.0, f64::INFINITY, Method::GaussKronrod21), ProtonError::Diverged { .. }));
+        // Rapid oscillation uses up the evaluation budget
+        assert!(matches!(failure("cos(x)", 0.0, 1e6, Method::GaussKronrod21), ProtonError::NotConverged { .. }));
+        for method in METHODS {
+            assert!(matches!(failure("x", f64::NAN, 1.0, method), ProtonError::InvalidBound(bound) if bound.is_nan()));
+            assert!(matches!(failure("x", 0.0, f64::NAN, method), ProtonError::InvalidBound(bound) if bound.is_nan()));
+        }
+    }
+
+    #[test]
+    fn test_bounds_and_variables() {
+        assert_integral("x", 2.0, 0.0, -2.0, Method::GaussKronrod21);
+        let empty = quadrature(&parse("x").unwrap(), "x", 1.0, 1.0, &HashMap::new(), Method::AdaptiveSimpson).unwrap();
+        assert_eq!((empty.value, empty.evaluations), (0.0, 0));
+
+        let vars = HashMap::from([("a".to_string(), 2.0)]);
+        let estimate = quadrature(&parse("a * x").unwrap(), "x", 0.0, 1.0, &vars, Method::GaussKronrod15).unwrap();
+        assert!((estimate.value - 1.0).abs() < 1e-12);
+        assert!(matches!(
+            quadrature(&parse("a * x").unwrap(), "x", 0.0, 1.0, &HashMap::new(), Method::GaussKronrod15),
+            Err(ProtonError::UnboundVariables(_))
+        ));
+    }
+}